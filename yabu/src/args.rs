@@ -1,10 +1,12 @@
 use argh::{FromArgValue, FromArgs};
-use std::fmt::Debug;
+use std::{fmt::Debug, path::PathBuf, str::FromStr};
 use time::OffsetDateTime;
 use url::Url;
 use yabusame::{
     connection::{default_server, url_from_str},
-    Delta, Priority, TaskId,
+    list::validate_list_name,
+    recurrence::Recurrence,
+    validate_tag, Delta, Priority, SetDelta, Sort, SortKey, TaskId,
 };
 
 use crate::datetime::{delta_time_from_str, offset_date_time_from_str};
@@ -17,6 +19,66 @@ fn delta_from_str<T: FromArgValue>(s: &str) -> Result<Delta<T>, String> {
     }
 }
 
+/// `-` or `none` removes the value.
+fn delta_option_from_str<T: FromArgValue>(s: &str) -> Result<Delta<Option<T>>, String> {
+    if s == "-" || s.eq_ignore_ascii_case("none") {
+        Ok(Delta::Changed(None))
+    } else {
+        Ok(Delta::Changed(Some(T::from_arg_value(s)?)))
+    }
+}
+
+fn tag_from_str(s: &str) -> Result<String, String> {
+    validate_tag(s).map_err(|err| err.to_string())?;
+    Ok(s.to_string())
+}
+
+/// `+tag` adds a tag and `-tag` removes one. A bare `tag` is also added.
+fn tag_delta_from_str(s: &str) -> Result<SetDelta<String>, String> {
+    if let Some(tag) = s.strip_prefix('-') {
+        Ok(SetDelta::Remove(tag_from_str(tag)?))
+    } else {
+        Ok(SetDelta::Add(tag_from_str(
+            s.strip_prefix('+').unwrap_or(s),
+        )?))
+    }
+}
+
+fn list_name_from_str(s: &str) -> Result<String, String> {
+    validate_list_name(s).map_err(|err| err.to_string())?;
+    Ok(s.to_string())
+}
+
+/// `+id` adds a dependency and `-id` removes one. A bare `id` is also added.
+fn dependency_delta_from_str(s: &str) -> Result<SetDelta<TaskId>, String> {
+    if let Some(task_id) = s.strip_prefix('-') {
+        Ok(SetDelta::Remove(TaskId::from_arg_value(task_id)?))
+    } else {
+        Ok(SetDelta::Add(TaskId::from_arg_value(
+            s.strip_prefix('+').unwrap_or(s),
+        )?))
+    }
+}
+
+/// `key` sorts in ascending order and `key:desc` in descending order.
+fn sort_from_str(s: &str) -> Result<Sort, String> {
+    let (key, descending) = match s.rsplit_once(':') {
+        Some((key, "asc")) => (key, false),
+        Some((key, "desc")) => (key, true),
+        Some((_, direction)) => {
+            return Err(format!(
+                "unknown sort direction `{direction}` (expected `asc` or `desc`)"
+            ))
+        }
+        None => (s, false),
+    };
+
+    Ok(Sort {
+        key: SortKey::from_str(key).map_err(|err| err.to_string())?,
+        descending,
+    })
+}
+
 /// Terminal client for the Yabusame todo list.
 #[derive(Debug, FromArgs)]
 pub struct Args {
@@ -29,6 +91,19 @@ pub struct Args {
     )]
     pub server: Url,
 
+    #[argh(
+        option,
+        description = "PEM file with extra certificates to trust for `yabus://` servers"
+    )]
+    pub ca_file: Option<PathBuf>,
+
+    #[argh(
+        option,
+        short = 'l',
+        description = "name of the list to add and show tasks in (use '-' for every list)"
+    )]
+    pub list: Option<String>,
+
     #[argh(subcommand)]
     pub subcommand: Subcommand,
 }
@@ -40,6 +115,9 @@ pub enum Subcommand {
     List(List),
     Update(Update),
     Remove(Remove),
+    Show(Show),
+    Edit(Edit),
+    Lists(Lists),
 }
 
 #[derive(Debug, FromArgs)]
@@ -61,13 +139,117 @@ pub struct Add {
     )]
     pub due_date: Option<OffsetDateTime>,
 
+    #[argh(
+        option,
+        short = 't',
+        description = "tag to attach to this task (can be repeated)",
+        from_str_fn(tag_from_str)
+    )]
+    pub tags: Vec<String>,
+
+    #[argh(
+        option,
+        short = 'e',
+        description = "repeat this task once it's completed, such as `day`, `2 weeks`, or `week on mon,thu`"
+    )]
+    pub every: Option<Recurrence>,
+
+    #[argh(
+        option,
+        description = "stop repeating after this date (needs `--every`)",
+        from_str_fn(offset_date_time_from_str)
+    )]
+    pub until: Option<OffsetDateTime>,
+
+    #[argh(
+        option,
+        description = "repeat this many times in total, counting this one (needs `--every`)"
+    )]
+    pub times: Option<u32>,
+
+    #[argh(option, description = "make this a subtask of another task")]
+    pub parent: Option<TaskId>,
+
+    #[argh(
+        option,
+        short = 'b',
+        description = "task which has to be completed before this one can start (can be repeated)"
+    )]
+    pub blocked_by: Vec<TaskId>,
+
     #[argh(positional)]
     pub description: String,
 }
 
 #[derive(Debug, FromArgs)]
 #[argh(subcommand, name = "list", description = "")]
-pub struct List {}
+pub struct List {
+    #[argh(switch, description = "only show complete tasks")]
+    pub complete: bool,
+
+    #[argh(switch, description = "only show incomplete tasks")]
+    pub incomplete: bool,
+
+    #[argh(
+        switch,
+        description = "only show incomplete tasks which are past their due date"
+    )]
+    pub overdue: bool,
+
+    #[argh(
+        switch,
+        description = "only show incomplete tasks which aren't waiting on any others"
+    )]
+    pub ready: bool,
+
+    #[argh(option, description = "only show tasks with at least this priority")]
+    pub min_priority: Option<Priority>,
+
+    #[argh(option, description = "only show tasks with at most this priority")]
+    pub max_priority: Option<Priority>,
+
+    #[argh(
+        option,
+        description = "only show tasks due before this date",
+        from_str_fn(offset_date_time_from_str)
+    )]
+    pub due_before: Option<OffsetDateTime>,
+
+    #[argh(
+        option,
+        description = "only show tasks due after this date",
+        from_str_fn(offset_date_time_from_str)
+    )]
+    pub due_after: Option<OffsetDateTime>,
+
+    #[argh(
+        option,
+        short = 'f',
+        description = "only show tasks whose description contains this text"
+    )]
+    pub find: Option<String>,
+
+    #[argh(
+        option,
+        short = 't',
+        description = "only show tasks with this tag (can be repeated)",
+        from_str_fn(tag_from_str)
+    )]
+    pub tags: Vec<String>,
+
+    #[argh(
+        option,
+        description = "sort by `id`, `due`, or `priority`, optionally followed by `:desc` (can be repeated)",
+        from_str_fn(sort_from_str)
+    )]
+    pub sort: Vec<Sort>,
+
+    #[argh(option, short = 'n', description = "show at most this many tasks")]
+    pub limit: Option<u32>,
+
+    #[argh(option, description = "skip this many tasks", default = "0")]
+    pub offset: u32,
+}
 
 #[derive(Debug, FromArgs)]
 #[argh(subcommand, name = "update", description = "")]
@@ -108,6 +290,45 @@ pub struct Update {
     )]
     pub description: Delta<String>,
 
+    #[argh(
+        option,
+        short = 't',
+        description = "tag to add (`+tag`) or remove (`-tag`) (can be repeated)",
+        from_str_fn(tag_delta_from_str)
+    )]
+    pub tag: Vec<SetDelta<String>>,
+
+    #[argh(
+        option,
+        short = 'e',
+        description = "how often to repeat this task, such as `week on mon` (use '-' or 'none' to stop)",
+        default = "Default::default()",
+        from_str_fn(delta_option_from_str)
+    )]
+    pub every: Delta<Option<Recurrence>>,
+
+    #[argh(
+        option,
+        description = "make this a subtask of another task (use '-' or 'none' to make it top-level)",
+        default = "Default::default()",
+        from_str_fn(delta_option_from_str)
+    )]
+    pub parent: Delta<Option<TaskId>>,
+
+    #[argh(
+        option,
+        short = 'b',
+        description = "task this one has to wait for (`+id`), or no longer waits for (`-id`) (can be repeated)",
+        from_str_fn(dependency_delta_from_str)
+    )]
+    pub blocked_by: Vec<SetDelta<TaskId>>,
+
+    #[argh(
+        option,
+        description = "name of the list to move this task to (use '-' to take it out of its list)"
+    )]
+    pub move_to: Option<String>,
+
     #[argh(positional)]
     pub task_id: TaskId,
 }
@@ -118,3 +339,84 @@ pub struct Remove {
     #[argh(positional)]
     pub task_id: TaskId,
 }
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "show", description = "show a task and its notes")]
+pub struct Show {
+    #[argh(positional)]
+    pub task_id: TaskId,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "edit",
+    description = "edit a task's notes in $VISUAL or $EDITOR"
+)]
+pub struct Edit {
+    #[argh(positional)]
+    pub task_id: TaskId,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "lists", description = "manage lists of tasks")]
+pub struct Lists {
+    #[argh(subcommand)]
+    pub subcommand: ListsSubcommand,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+pub enum ListsSubcommand {
+    Show(ShowLists),
+    Add(AddList),
+    Rename(RenameList),
+    Archive(ArchiveList),
+    Remove(RemoveList),
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "show", description = "show every list")]
+pub struct ShowLists {}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "add", description = "add a list")]
+pub struct AddList {
+    #[argh(positional, from_str_fn(list_name_from_str))]
+    pub name: String,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "rename", description = "rename a list")]
+pub struct RenameList {
+    #[argh(positional)]
+    pub name: String,
+
+    #[argh(positional, from_str_fn(list_name_from_str))]
+    pub new_name: String,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "archive",
+    description = "hide a list, without removing its tasks"
+)]
+pub struct ArchiveList {
+    #[argh(switch, description = "bring the list back instead")]
+    pub undo: bool,
+
+    #[argh(positional)]
+    pub name: String,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "remove",
+    description = "remove a list along with every task in it"
+)]
+pub struct RemoveList {
+    #[argh(positional)]
+    pub name: String,
+}
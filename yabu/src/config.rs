@@ -0,0 +1,47 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::{env, fs, io, path::PathBuf};
+
+pub const TOKEN_ENV_VAR: &str = "YABU_TOKEN";
+
+/// Settings read from `$CONFIG_DIR/yabu/config.toml`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// The API token to give to `yabuserver`.
+    /// Overridden by the `YABU_TOKEN` environment variable.
+    pub token: Option<String>,
+    /// Extra certificates to trust for `yabus://` servers.
+    /// Overridden by `--ca-file`.
+    pub ca_file: Option<PathBuf>,
+    /// The name of the list to add and show tasks in. Overridden by `--list`.
+    pub default_list: Option<String>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("yabu").join("config.toml"))
+    }
+
+    /// A missing config file is treated as an empty one.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = match Self::path() {
+            Some(path) => match fs::read_to_string(&path) {
+                Ok(s) => toml::from_str(&s)
+                    .with_context(|| format!("couldn't parse {}", path.display()))?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Self::default(),
+                Err(err) => {
+                    return Err(err).with_context(|| format!("couldn't read {}", path.display()))
+                }
+            },
+
+            None => Self::default(),
+        };
+
+        if let Ok(token) = env::var(TOKEN_ENV_VAR) {
+            config.token = Some(token);
+        }
+
+        Ok(config)
+    }
+}
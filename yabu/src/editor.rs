@@ -0,0 +1,46 @@
+use anyhow::{anyhow, bail, Context};
+use std::{env, fs, io::Write, process};
+
+/// Used when neither `$VISUAL` nor `$EDITOR` is set.
+const FALLBACK_EDITOR: &str = "vi";
+
+/// Opens `text` in the user's editor and returns it as it was saved.
+/// `file_name` gives editors a hint about the syntax, such as `notes.md`.
+pub fn edit(text: &str, file_name: &str) -> anyhow::Result<String> {
+    let editor = ["VISUAL", "EDITOR"]
+        .into_iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| FALLBACK_EDITOR.to_string());
+
+    // Editors are often given arguments, such as `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or_else(|| anyhow!("no editor to run"))?;
+
+    // Created with a random name and only readable by the user, so other
+    // users can't read the notes or have a file of ours overwritten instead.
+    // Removed when dropped.
+    let mut file = tempfile::Builder::new()
+        .prefix("yabu-")
+        .suffix(&format!("-{file_name}"))
+        .tempfile()
+        .context("couldn't create a temporary file")?;
+
+    file.write_all(text.as_bytes())
+        .and_then(|_| file.flush())
+        .with_context(|| format!("couldn't write {}", file.path().display()))?;
+
+    let status = process::Command::new(program)
+        .args(words)
+        .arg(file.path())
+        .status()
+        .with_context(|| format!("couldn't run `{editor}`"))?;
+
+    if !status.success() {
+        bail!("`{editor}` exited with {status}; nothing was saved");
+    }
+
+    // Read by path, since some editors save by replacing the file
+    fs::read_to_string(file.path())
+        .with_context(|| format!("couldn't read {}", file.path().display()))
+}
@@ -3,33 +3,216 @@
 #![warn(unused_imports, unused_must_use)]
 
 mod args;
+mod config;
 mod datetime;
+mod editor;
 
-use args::Subcommand;
+use anyhow::{anyhow, bail};
+use args::{ListsSubcommand, Subcommand};
 use comfy_table::{presets::NOTHING, Attribute, Cell, CellAlignment, Color, Table};
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::BTreeSet};
 use yabusame::{
-    connection::ClientConnection, Delta, Message, Priority, Response, Task, TaskDelta,
-    DATE_TIME_FORMAT,
+    connection::{ClientConnection, ConnectionOptions},
+    list::{List, ListDelta, ListId},
+    nest_subtasks,
+    recurrence::Recurrence,
+    Delta, Message, Priority, Response, Task, TaskDelta, TaskId, TaskQuery, DATE_TIME_FORMAT,
 };
 
-use crate::args::Args;
+use crate::{args::Args, config::Config};
+
+const CLIENT_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+async fn fetch_task(connection: &ClientConnection, task_id: TaskId) -> anyhow::Result<Task> {
+    match connection.send(Message::Get(task_id)).await? {
+        Response::Task(task) => Ok(task),
+        Response::Error(err) => Err(err.into()),
+        response => bail!("unexpected response from the server: {response:?}"),
+    }
+}
+
+async fn fetch_lists(connection: &ClientConnection) -> anyhow::Result<Vec<List>> {
+    match connection.send(Message::Lists).await? {
+        Response::Lists(lists) => Ok(lists),
+        Response::Error(err) => Err(err.into()),
+        response => bail!("unexpected response from the server: {response:?}"),
+    }
+}
+
+/// Looks up the ID of the user's list with the given name.
+async fn find_list(connection: &ClientConnection, name: &str) -> anyhow::Result<ListId> {
+    let lists = fetch_lists(connection).await?;
+
+    match lists.into_iter().find(|list| list.name == name) {
+        Some(list) => list.id.ok_or_else(|| anyhow!("list `{name}` has no ID")),
+        None => bail!("there's no list named `{name}`; use `yabu lists add {name}` to add one"),
+    }
+}
+
+/// Like `find_list`, but `-` (or no name at all) stands for no list.
+async fn find_list_or_none(
+    connection: &ClientConnection,
+    name: Option<&str>,
+) -> anyhow::Result<Option<ListId>> {
+    match name {
+        None | Some("-") => Ok(None),
+        Some(name) => Ok(Some(find_list(connection, name).await?)),
+    }
+}
+
+/// Prints everything about a task, followed by its notes.
+async fn show_task(connection: &ClientConnection, task: Task) -> anyhow::Result<()> {
+    let task_id = task.id_or_error()?;
+    let mut table = Table::new();
+    table.load_preset(NOTHING);
+
+    let mut add_row = |name: &str, value: String| {
+        if !value.is_empty() {
+            table.add_row(vec![
+                Cell::new(name).add_attribute(Attribute::Bold),
+                Cell::new(value),
+            ]);
+        }
+    };
+
+    add_row("task", task_id.to_string());
+    add_row("description", task.description.to_string());
+    add_row(
+        "status",
+        if task.complete {
+            "complete"
+        } else {
+            "incomplete"
+        }
+        .to_string(),
+    );
+    add_row("priority", task.priority.as_str().to_string());
+
+    if let Some(due_date) = task.due_date {
+        add_row("due date", due_date.format(&DATE_TIME_FORMAT)?);
+    }
+
+    if let Some(recurrence) = &task.recurrence {
+        add_row("repeats", format!("every {recurrence}"));
+    }
+
+    if let Some(parent) = task.parent {
+        add_row("subtask of", format!("#{parent}"));
+    }
+
+    if let Some(progress) = task.progress {
+        add_row("subtasks", format!("{progress} complete"));
+    }
+
+    let task_ids = |task_ids: &BTreeSet<TaskId>| {
+        (task_ids.iter())
+            .map(|task_id| format!("#{task_id}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    add_row("depends on", task_ids(&task.depends_on));
+    add_row("blocked by", task_ids(&task.blocked_by));
+    add_row(
+        "tags",
+        task.tags.iter().cloned().collect::<Vec<_>>().join(", "),
+    );
+
+    if let Some(list_id) = task.list {
+        let lists = fetch_lists(connection).await?;
+        let list = lists.into_iter().find(|list| list.id == Some(list_id));
+        add_row(
+            "list",
+            list.map_or_else(|| list_id.to_string(), |list| list.name),
+        );
+    }
+
+    println!("{table}");
+    println!();
+
+    match &task.notes {
+        Some(notes) => println!("{notes}"),
+        None => println!("no notes; use `yabu edit {task_id}` to add some"),
+    }
+
+    Ok(())
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = argh::from_env::<Args>();
-    let mut connection = ClientConnection::new(&args.server).await?;
+    let config = Config::load()?;
+
+    let options = ConnectionOptions {
+        token: config.token,
+        ca_file: args.ca_file.or(config.ca_file),
+        ..ConnectionOptions::new(CLIENT_NAME)
+    };
+    let connection = ClientConnection::new(&args.server, &options).await?;
+    let list_name = args.list.or(config.default_list);
+
+    // Only used to pick a more helpful message when there are no tasks
+    let mut is_filtered = false;
 
     let message = match args.subcommand {
-        Subcommand::Add(new_args) => Message::Add(Task::new(
-            None,
-            false,
-            new_args.description,
-            new_args.priority,
-            new_args.due_date,
-        )),
+        Subcommand::Add(new_args) => {
+            let recurrence = match new_args.every {
+                Some(recurrence) => Some(Recurrence {
+                    until: new_args.until,
+                    count: new_args.times,
+                    ..recurrence
+                }),
+
+                None if new_args.until.is_some() || new_args.times.is_some() => {
+                    bail!("`--until` and `--times` need `--every`")
+                }
+
+                None => None,
+            };
+
+            Message::Add(Task {
+                list: find_list_or_none(&connection, list_name.as_deref()).await?,
+                tags: new_args.tags.into_iter().collect(),
+                recurrence,
+                parent: new_args.parent,
+                depends_on: new_args.blocked_by.into_iter().collect(),
+                ..Task::new(
+                    None,
+                    false,
+                    new_args.description,
+                    new_args.priority,
+                    new_args.due_date,
+                )
+            })
+        }
 
-        Subcommand::List(_) => Message::List,
+        Subcommand::List(list_args) => {
+            let complete = match (list_args.complete, list_args.incomplete) {
+                (true, true) => bail!("`--complete` and `--incomplete` can't be used together"),
+                (true, false) => Some(true),
+                (false, true) => Some(false),
+                (false, false) => None,
+            };
+
+            let query = TaskQuery {
+                complete,
+                min_priority: list_args.min_priority,
+                max_priority: list_args.max_priority,
+                due_before: list_args.due_before,
+                due_after: list_args.due_after,
+                overdue: list_args.overdue,
+                text: list_args.find,
+                tags: list_args.tags.into_iter().collect(),
+                ready: list_args.ready,
+                list: find_list_or_none(&connection, list_name.as_deref()).await?,
+                sort: list_args.sort,
+                limit: list_args.limit,
+                offset: list_args.offset,
+            };
+
+            is_filtered = query.has_filters();
+            Message::Query(query)
+        }
 
         Subcommand::Update(update_args) => Message::Update(
             update_args.task_id,
@@ -42,17 +225,90 @@ async fn main() -> anyhow::Result<()> {
                 },
                 priority: update_args.priority,
                 due_date: update_args.due_date,
+                tags: update_args.tag,
+                recurrence: update_args.every,
+                parent: update_args.parent,
+                depends_on: update_args.blocked_by,
+                notes: Delta::Unchanged,
+                list: match update_args.move_to {
+                    Some(name) => {
+                        Delta::Changed(find_list_or_none(&connection, Some(&name)).await?)
+                    }
+                    None => Delta::Unchanged,
+                },
             },
         ),
 
         Subcommand::Remove(remove_args) => Message::Remove(remove_args.task_id),
+
+        Subcommand::Show(show_args) => Message::Get(show_args.task_id),
+
+        Subcommand::Edit(edit_args) => {
+            let task = fetch_task(&connection, edit_args.task_id).await?;
+            let previous = task.notes.as_deref().unwrap_or_default();
+            let file_name = format!("task-{}.md", edit_args.task_id);
+
+            let edited = editor::edit(previous, &file_name)?;
+
+            // Editors usually add a trailing newline, and notes saved
+            // through the API might have one too
+            let notes = edited.trim_end();
+
+            if notes == previous.trim_end() {
+                println!("notes unchanged");
+                return Ok(());
+            }
+
+            Message::Update(
+                edit_args.task_id,
+                TaskDelta {
+                    notes: Delta::Changed((!notes.is_empty()).then(|| notes.to_string())),
+                    ..TaskDelta::default()
+                },
+            )
+        }
+
+        Subcommand::Lists(lists_args) => match lists_args.subcommand {
+            ListsSubcommand::Show(_) => Message::Lists,
+            ListsSubcommand::Add(add_args) => Message::AddList(List::new(add_args.name)),
+
+            ListsSubcommand::Rename(rename_args) => Message::UpdateList(
+                find_list(&connection, &rename_args.name).await?,
+                ListDelta {
+                    name: Delta::Changed(rename_args.new_name),
+                    ..ListDelta::default()
+                },
+            ),
+
+            ListsSubcommand::Archive(archive_args) => Message::UpdateList(
+                find_list(&connection, &archive_args.name).await?,
+                ListDelta {
+                    archived: Delta::Changed(!archive_args.undo),
+                    ..ListDelta::default()
+                },
+            ),
+
+            ListsSubcommand::Remove(remove_args) => {
+                Message::RemoveList(find_list(&connection, &remove_args.name).await?)
+            }
+        },
     };
 
+    let is_add = matches!(message, Message::Add(_) | Message::AddList(_));
+    let is_show = matches!(message, Message::Get(_));
+
     match connection.send(message).await? {
         Response::Nothing => {}
 
+        Response::Task(task) if is_add => println!("added task {}", task.id_or_error()?),
+        Response::Task(task) if is_show => show_task(&connection, task).await?,
+        Response::Task(_) => {}
+
         Response::Tasks(tasks) => {
-            if tasks.is_empty() {
+            if tasks.is_empty() && is_filtered {
+                println!("no tasks matched");
+                return Ok(());
+            } else if tasks.is_empty() {
                 println!("you have no tasks; use `yabu add [description]` to add one");
                 return Ok(());
             }
@@ -64,6 +320,9 @@ async fn main() -> anyhow::Result<()> {
                 "description",
                 "priority",
                 "due date",
+                "repeats",
+                "blocked by",
+                "tags",
             ]);
 
             table
@@ -76,7 +335,7 @@ async fn main() -> anyhow::Result<()> {
                 .unwrap()
                 .set_cell_alignment(CellAlignment::Center);
 
-            for task in tasks {
+            for (depth, task) in nest_subtasks(tasks) {
                 let completed = if task.complete { "X" } else { " " };
 
                 let mut priority = Cell::new(task.priority.as_str());
@@ -96,7 +355,28 @@ async fn main() -> anyhow::Result<()> {
                     None => "".into(),
                 };
 
-                let mut description = Cell::new(&task.description);
+                let repeats = match &task.recurrence {
+                    Some(recurrence) => format!("every {recurrence}"),
+                    None => String::new(),
+                };
+
+                let blocked_by = task
+                    .blocked_by
+                    .iter()
+                    .map(|task_id| format!("#{task_id}"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                let tags = task.tags.iter().cloned().collect::<Vec<_>>().join(", ");
+
+                let indent = "  ".repeat(depth);
+
+                let mut description = match task.progress {
+                    Some(progress) => {
+                        Cell::new(format!("{indent}{} ({progress})", task.description))
+                    }
+                    None => Cell::new(format!("{indent}{}", task.description)),
+                };
 
                 if task.complete {
                     description = description.add_attribute(Attribute::CrossedOut);
@@ -108,6 +388,36 @@ async fn main() -> anyhow::Result<()> {
                     description,
                     priority,
                     Cell::new(due_date),
+                    Cell::new(repeats),
+                    Cell::new(blocked_by),
+                    Cell::new(tags),
+                ]);
+            }
+
+            println!("{table}");
+        }
+
+        Response::List(list) if is_add => println!("added list `{}`", list.name),
+        Response::List(_) => {}
+
+        Response::Lists(lists) => {
+            if lists.is_empty() {
+                println!("you have no lists; use `yabu lists add [name]` to add one");
+                return Ok(());
+            }
+
+            let mut table = Table::new();
+            table
+                .load_preset(NOTHING)
+                .set_header(vec!["list", "name", "archived"]);
+
+            for list in lists {
+                let archived = if list.archived { "X" } else { " " };
+
+                table.add_row(vec![
+                    Cell::new(list.id.map_or_else(String::new, |id| id.to_string())),
+                    Cell::new(list.name),
+                    Cell::new(archived),
                 ]);
             }
 
@@ -115,6 +425,8 @@ async fn main() -> anyhow::Result<()> {
         }
 
         Response::Error(err) => return Err(err.into()),
+
+        response => bail!("unexpected response from the server: {response:?}"),
     }
 
     Ok(())
@@ -0,0 +1,250 @@
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::YabuError;
+
+/// Sent by clients at the start of every connection, followed by a single
+/// byte holding the framing version. Clients using the legacy framing don't
+/// send this at all.
+pub const FRAME_MAGIC: [u8; 4] = *b"YABU";
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Framing {
+    /// Every payload is prefixed with its length as a little-endian `u16`.
+    /// Only kept around so that older clients can still be understood.
+    Legacy,
+    /// Every payload is prefixed with its length as a little-endian `u32`.
+    V2,
+}
+
+impl Framing {
+    pub const CURRENT: Self = Self::V2;
+
+    pub fn version(self) -> u8 {
+        match self {
+            Framing::Legacy => 1,
+            Framing::V2 => 2,
+        }
+    }
+
+    pub fn from_version(version: u8) -> Result<Self, YabuError> {
+        match version {
+            1 => Ok(Self::Legacy),
+            2 => Ok(Self::V2),
+            _ => Err(YabuError::UnsupportedFraming(version)),
+        }
+    }
+
+    fn length_size(self) -> usize {
+        match self {
+            Framing::Legacy => 2,
+            Framing::V2 => 4,
+        }
+    }
+}
+
+/// Reads and writes length-prefixed JSON frames.
+/// Used for both directions of a connection.
+#[derive(Clone, Debug)]
+pub struct Codec {
+    framing: Framing,
+    max_frame_size: u32,
+    // Bytes that were read while sniffing the framing, but which
+    // actually belong to the first frame
+    pending: Vec<u8>,
+}
+
+impl Codec {
+    pub fn new(framing: Framing) -> Self {
+        Self {
+            framing,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            pending: Vec::new(),
+        }
+    }
+
+    #[must_use = "this returns a new codec without changing the original"]
+    pub fn with_max_frame_size(mut self, max_frame_size: u32) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// The largest payload this codec will send or accept, in bytes.
+    pub fn max_frame_size(&self) -> u32 {
+        match self.framing {
+            Framing::Legacy => self.max_frame_size.min(u16::MAX.into()),
+            Framing::V2 => self.max_frame_size,
+        }
+    }
+
+    /// Announces the current framing at the start of a new connection.
+    pub async fn connect<W: AsyncWriteExt + Unpin>(mut socket: W) -> Result<Self, YabuError> {
+        let framing = Framing::CURRENT;
+        socket.write_all(&FRAME_MAGIC).await?;
+        socket.write_u8(framing.version()).await?;
+        Ok(Self::new(framing))
+    }
+
+    /// Figures out which framing the client on the other end of a new
+    /// connection is using.
+    pub async fn accept<R: AsyncReadExt + Unpin>(mut socket: R) -> Result<Self, YabuError> {
+        let mut magic = [0u8; FRAME_MAGIC.len()];
+        socket.read_exact(&mut magic).await?;
+
+        if magic == FRAME_MAGIC {
+            let framing = Framing::from_version(socket.read_u8().await?)?;
+            return Ok(Self::new(framing));
+        }
+
+        // Legacy clients jump straight into sending a frame. This is unambiguous
+        // since the third byte of a legacy frame is the start of a JSON value,
+        // which can never be `B`.
+        Ok(Self {
+            pending: magic.to_vec(),
+            ..Self::new(Framing::Legacy)
+        })
+    }
+
+    async fn read_exact<R: AsyncReadExt + Unpin>(
+        &mut self,
+        mut socket: R,
+        buf: &mut [u8],
+    ) -> Result<(), YabuError> {
+        let from_pending = self.pending.len().min(buf.len());
+        buf[..from_pending].copy_from_slice(&self.pending[..from_pending]);
+        self.pending.drain(..from_pending);
+
+        socket.read_exact(&mut buf[from_pending..]).await?;
+        Ok(())
+    }
+
+    pub async fn read<T: DeserializeOwned, R: AsyncReadExt + Unpin>(
+        &mut self,
+        mut socket: R,
+    ) -> Result<T, YabuError> {
+        // read the payload length
+        let mut length_buf = [0u8; 4];
+        let length_size = self.framing.length_size();
+        self.read_exact(&mut socket, &mut length_buf[..length_size])
+            .await?;
+        let length = u32::from_le_bytes(length_buf);
+
+        // make sure a malicious or confused peer can't make us allocate
+        // an arbitrary amount of memory
+        if length > self.max_frame_size() {
+            return Err(YabuError::FrameTooLarge {
+                size: length as usize,
+                max: self.max_frame_size(),
+            });
+        }
+
+        // now for the payload
+        let mut buf = vec![0; length as usize];
+        self.read_exact(&mut socket, &mut buf).await?;
+        Ok(serde_json::from_slice::<T>(&buf)?)
+    }
+
+    /// Serializes a value into a complete frame, ready to be written.
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, YabuError> {
+        let payload = serde_json::to_vec(value)?;
+
+        let length = u32::try_from(payload.len())
+            .ok()
+            .filter(|&length| length <= self.max_frame_size())
+            .ok_or(YabuError::FrameTooLarge {
+                size: payload.len(),
+                max: self.max_frame_size(),
+            })?;
+
+        let length_bytes = length.to_le_bytes();
+        let mut frame = length_bytes[..self.framing.length_size()].to_vec();
+        frame.extend_from_slice(&payload);
+        Ok(frame)
+    }
+
+    pub async fn write<T: Serialize, W: AsyncWriteExt + Unpin>(
+        &self,
+        mut socket: W,
+        value: &T,
+    ) -> Result<(), YabuError> {
+        let frame = self.encode(value)?;
+
+        // Streams like TLS buffer writes, so make sure the frame actually gets sent
+        socket.write_all(&frame).await?;
+        socket.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value() -> Vec<String> {
+        vec!["water the plants".to_string(), "buy more pots".to_string()]
+    }
+
+    #[tokio::test]
+    async fn current_framing_round_trip() {
+        let mut stream = Vec::new();
+        let codec = Codec::connect(&mut stream).await.unwrap();
+        codec.write(&mut stream, &value()).await.unwrap();
+
+        let mut received = &stream[..];
+        let mut codec = Codec::accept(&mut received).await.unwrap();
+        assert_eq!(codec.framing(), Framing::V2);
+        let read: Vec<String> = codec.read(&mut received).await.unwrap();
+        assert_eq!(read, value());
+        assert!(received.is_empty());
+    }
+
+    #[tokio::test]
+    async fn legacy_framing_is_sniffed() {
+        // Legacy clients send frames straight away, without the magic
+        let stream = Codec::new(Framing::Legacy).encode(&value()).unwrap();
+        let length = serde_json::to_vec(&value()).unwrap().len();
+        assert_eq!(stream[..2], (length as u16).to_le_bytes());
+
+        let mut received = &stream[..];
+        let mut codec = Codec::accept(&mut received).await.unwrap();
+        assert_eq!(codec.framing(), Framing::Legacy);
+        let read: Vec<String> = codec.read(&mut received).await.unwrap();
+        assert_eq!(read, value());
+        assert!(received.is_empty());
+    }
+
+    #[tokio::test]
+    async fn oversized_frames_are_rejected() {
+        let frame = Codec::new(Framing::V2).encode(&value()).unwrap();
+        let codec = Codec::new(Framing::V2).with_max_frame_size(8);
+
+        let mut received = &frame[..];
+        let read = codec.clone().read::<Vec<String>, _>(&mut received).await;
+        assert!(matches!(read, Err(YabuError::FrameTooLarge { max: 8, .. })));
+        // Only the length was read, nothing was allocated for the payload
+        assert_eq!(received.len(), frame.len() - 4);
+
+        let encoded = codec.encode(&value());
+        assert!(matches!(
+            encoded,
+            Err(YabuError::FrameTooLarge { max: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn legacy_frames_are_limited_by_their_length() {
+        let codec = Codec::new(Framing::Legacy);
+        assert_eq!(codec.max_frame_size(), u32::from(u16::MAX));
+
+        let value = "a".repeat(usize::from(u16::MAX));
+        assert!(matches!(
+            codec.encode(&value),
+            Err(YabuError::FrameTooLarge { max, .. }) if max == u32::from(u16::MAX)
+        ));
+    }
+}
@@ -1,14 +1,114 @@
-use tokio::net::{lookup_host, TcpSocket, TcpStream};
+use futures_core::Stream;
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{lookup_host, TcpSocket},
+    sync::{mpsc, oneshot},
+};
+use tokio_rustls::{rustls::ServerName, TlsConnector};
+
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use url::Url;
 
-use crate::{Message, Response, YabuError, DEFAULT_SERVER_PORT, URL_SCHEME};
+use crate::{
+    codec::Codec, tls, Capability, Hello, HelloResponse, Message, Request, RequestId, Response,
+    ServerFrame, TaskEvent, TaskQuery, YabuError, DEFAULT_SERVER_PORT, PROTOCOL_VERSION,
+    TLS_URL_SCHEME, UNIX_URL_SCHEME, URL_SCHEME,
+};
+
+/// Any stream a connection can be made over.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> AsyncStream for T {}
+
+/// Every capability this version of the client knows how to use.
+pub fn client_capabilities() -> BTreeSet<Capability> {
+    BTreeSet::new()
+}
+
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    /// Shown in the server's logs. Conventionally `name/version`.
+    pub client_name: Cow<'static, str>,
+    /// The API token to authenticate with.
+    pub token: Option<String>,
+    /// A PEM file with extra certificates to trust for `yabus://` servers.
+    pub ca_file: Option<PathBuf>,
+}
 
+impl ConnectionOptions {
+    pub fn new<S: Into<Cow<'static, str>>>(client_name: S) -> Self {
+        Self {
+            client_name: client_name.into(),
+            token: None,
+            ca_file: None,
+        }
+    }
+}
+
+/// A connection to a server. `ClientConnection::new` picks the stream based
+/// on the URL, but any stream can be used with `ClientConnection::from_stream`.
+///
+/// Frames are read and written on background tasks, so responses and
+/// subscription events can arrive over the same connection. Clones share
+/// the connection, and requests sent through them are answered concurrently.
+#[derive(Clone)]
 pub struct ClientConnection {
-    stream: TcpStream,
+    codec: Codec,
+    /// Encoded frames for `write_frames` to send.
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+    shared: Arc<Shared>,
+    capabilities: BTreeSet<Capability>,
+    authenticated: bool,
+}
+
+/// Where `read_frames` sends what it reads.
+#[derive(Default)]
+struct Routes {
+    /// Once set, nothing will be read from the connection anymore.
+    closed: bool,
+    pending: HashMap<RequestId, oneshot::Sender<Response>>,
+    subscriptions: HashMap<RequestId, mpsc::UnboundedSender<TaskEvent>>,
+}
+
+#[derive(Default)]
+struct Shared {
+    routes: Mutex<Routes>,
+    next_id: AtomicU32,
+}
+
+impl Shared {
+    fn routes(&self) -> MutexGuard<'_, Routes> {
+        // `Routes` is never left half-updated, so a poisoned lock is fine to use
+        self.routes.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Fails every pending request and ends every subscription.
+    fn close(&self) {
+        let mut routes = self.routes();
+        routes.closed = true;
+        routes.pending.clear();
+        routes.subscriptions.clear();
+    }
 }
 
 impl ClientConnection {
-    pub async fn new(server_url: &Url) -> Result<Self, YabuError> {
+    /// Connects to a server and performs the handshake.
+    pub async fn new(server_url: &Url, options: &ConnectionOptions) -> Result<Self, YabuError> {
+        if server_url.scheme() == UNIX_URL_SCHEME {
+            return Self::from_stream(connect_unix(server_url).await?, options).await;
+        }
+
         let host_str = server_url
             .host_str()
             .ok_or_else(|| YabuError::UrlHasNoHost(server_url.clone()))?;
@@ -18,14 +118,247 @@ impl ClientConnection {
             .next()
             .ok_or_else(|| YabuError::DnsLookupFailed(server_url.clone()))?;
 
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+
+        let stream = socket.connect(addr).await?;
+
+        let stream: Box<dyn AsyncStream> = if server_url.scheme() == TLS_URL_SCHEME {
+            let server_name = ServerName::try_from(host_str)
+                .map_err(|_| YabuError::InvalidServerName(host_str.to_string()))?;
+            let connector = TlsConnector::from(tls::client_config(options.ca_file.as_deref())?);
+            Box::new(connector.connect(server_name, stream).await?)
+        } else {
+            Box::new(stream)
+        };
+
+        Self::from_stream(stream, options).await
+    }
+
+    /// Performs the handshake over an already open stream.
+    pub async fn from_stream<S: AsyncStream + 'static>(
+        mut stream: S,
+        options: &ConnectionOptions,
+    ) -> Result<Self, YabuError> {
+        let mut codec = Codec::connect(&mut stream).await?;
+
+        let hello = Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client_name: options.client_name.clone(),
+            capabilities: client_capabilities(),
+            token: options.token.clone(),
+        };
+
+        codec.write(&mut stream, &hello).await?;
+
+        let (capabilities, max_frame_size, authenticated) = match codec.read(&mut stream).await? {
+            HelloResponse::Welcome {
+                protocol_version: _,
+                capabilities,
+                max_frame_size,
+                authenticated,
+            } => (capabilities, max_frame_size, authenticated),
+
+            HelloResponse::Incompatible { protocol_version } => {
+                return Err(YabuError::IncompatibleProtocol {
+                    client: PROTOCOL_VERSION,
+                    server: protocol_version,
+                })
+            }
+        };
+
+        let codec = codec.with_max_frame_size(max_frame_size);
+        let shared = Arc::new(Shared::default());
+        let (frames, frames_rx) = mpsc::unbounded_channel();
+        let (reader, writer) = io::split(stream);
+
+        tokio::spawn(read_frames(reader, codec.clone(), Arc::clone(&shared)));
+        tokio::spawn(write_frames(writer, frames_rx, Arc::clone(&shared)));
+
         Ok(Self {
-            stream: TcpSocket::new_v4()?.connect(addr).await?,
+            codec,
+            frames,
+            shared,
+            capabilities,
+            authenticated,
         })
     }
 
-    pub async fn send(&mut self, message: Message) -> Result<Response, YabuError> {
-        message.write_to_socket(&mut self.stream).await?;
-        Response::read_from_socket(&mut self.stream).await
+    /// The capabilities supported by both this client and the server.
+    pub fn capabilities(&self) -> &BTreeSet<Capability> {
+        &self.capabilities
+    }
+
+    /// Whether the server accepted this connection's token.
+    pub fn is_authenticated(&self) -> bool {
+        self.authenticated
+    }
+
+    /// Whether the connection has been closed, such as by the server going away.
+    /// Broken connections can't be used anymore and should be dropped.
+    pub fn is_broken(&self) -> bool {
+        self.shared.routes().closed
+    }
+
+    /// Sends a request, registering where its response (and its events,
+    /// for subscriptions) should go before it can possibly arrive.
+    fn start_request(
+        &self,
+        message: Message,
+        events: Option<mpsc::UnboundedSender<TaskEvent>>,
+    ) -> Result<(RequestId, oneshot::Receiver<Response>), YabuError> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let frame = self.codec.encode(&Request { id, message })?;
+        let (response_tx, response) = oneshot::channel();
+
+        {
+            let mut routes = self.shared.routes();
+
+            if routes.closed {
+                return Err(YabuError::NotSent);
+            }
+
+            routes.pending.insert(id, response_tx);
+
+            if let Some(events) = events {
+                routes.subscriptions.insert(id, events);
+            }
+        }
+
+        if self.frames.send(frame).is_err() {
+            let mut routes = self.shared.routes();
+            routes.pending.remove(&id);
+            routes.subscriptions.remove(&id);
+            return Err(YabuError::NotSent);
+        }
+
+        Ok((id, response))
+    }
+
+    pub async fn send(&self, message: Message) -> Result<Response, YabuError> {
+        let (_, response) = self.start_request(message, None)?;
+        response.await.map_err(|_| YabuError::ConnectionClosed)
+    }
+
+    /// Subscribes to changes to tasks matching `query`. See `Message::Subscribe`.
+    /// The subscription ends when it's dropped or when the connection is lost,
+    /// but it doesn't keep the rest of the connection busy.
+    pub async fn subscribe(&self, query: TaskQuery) -> Result<Subscription, YabuError> {
+        let (events_tx, events) = mpsc::unbounded_channel();
+        let (id, response) = self.start_request(Message::Subscribe(query), Some(events_tx))?;
+
+        let response = response.await.map_err(|_| YabuError::ConnectionClosed)?;
+
+        let err = match response {
+            Response::Nothing => {
+                return Ok(Subscription {
+                    id,
+                    events,
+                    codec: self.codec.clone(),
+                    frames: self.frames.clone(),
+                    shared: Arc::clone(&self.shared),
+                })
+            }
+
+            Response::Error(err) => err.into(),
+            response => YabuError::UnexpectedResponse(Box::new(response)),
+        };
+
+        self.shared.routes().subscriptions.remove(&id);
+        Err(err)
+    }
+}
+
+#[cfg(unix)]
+async fn connect_unix(server_url: &Url) -> Result<Box<dyn AsyncStream>, YabuError> {
+    let path = server_url
+        .to_file_path()
+        .map_err(|_| YabuError::InvalidSocketPath(server_url.clone()))?;
+
+    Ok(Box::new(UnixStream::connect(path).await?))
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(_server_url: &Url) -> Result<Box<dyn AsyncStream>, YabuError> {
+    Err(YabuError::UnixSocketsUnsupported)
+}
+
+/// Routes responses and events until the connection is closed.
+async fn read_frames<R: AsyncRead + Unpin>(mut reader: R, mut codec: Codec, shared: Arc<Shared>) {
+    while let Ok(frame) = codec.read(&mut reader).await {
+        match frame {
+            ServerFrame::Response { id, response } => {
+                if let Some(response_tx) = shared.routes().pending.remove(&id) {
+                    // The receiver is gone if the request was cancelled
+                    let _ = response_tx.send(response);
+                }
+            }
+
+            ServerFrame::Event {
+                subscription,
+                event,
+            } => {
+                if let Some(events) = shared.routes().subscriptions.get(&subscription) {
+                    let _ = events.send(event);
+                }
+            }
+        }
+    }
+
+    shared.close();
+}
+
+/// Writes frames until either the connection breaks or every clone
+/// of it and all of its subscriptions are dropped.
+async fn write_frames<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut frames: mpsc::UnboundedReceiver<Vec<u8>>,
+    shared: Arc<Shared>,
+) {
+    while let Some(frame) = frames.recv().await {
+        if writer.write_all(&frame).await.is_err() || writer.flush().await.is_err() {
+            break;
+        }
+    }
+
+    // Lets the server know we're done, which also stops `read_frames`
+    let _ = writer.shutdown().await;
+    shared.close();
+}
+
+/// Changes to tasks matching a query. Created by `ClientConnection::subscribe`.
+pub struct Subscription {
+    id: RequestId,
+    events: mpsc::UnboundedReceiver<TaskEvent>,
+    codec: Codec,
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+    shared: Arc<Shared>,
+}
+
+impl Stream for Subscription {
+    type Item = TaskEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<TaskEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.shared.routes().subscriptions.remove(&self.id);
+
+        // Nobody waits for the response, so it's dropped once it arrives
+        let request = Request {
+            id: self.shared.next_id.fetch_add(1, Ordering::Relaxed),
+            message: Message::Unsubscribe(self.id),
+        };
+
+        if let Ok(frame) = self.codec.encode(&request) {
+            let _ = self.frames.send(frame);
+        }
     }
 }
 
@@ -34,13 +367,24 @@ pub fn default_server() -> Url {
         .expect("default server URL failed to parse")
 }
 
+fn is_yabu_scheme(scheme: &str) -> bool {
+    [URL_SCHEME, TLS_URL_SCHEME, UNIX_URL_SCHEME].contains(&scheme)
+}
+
 pub fn url_from_str(s: &str) -> Result<Url, String> {
     let mut maybe_url = Url::parse(s).map_err(|e| e.to_string());
 
-    if let Ok(url) = &maybe_url && url.scheme() == URL_SCHEME {
+    if let Ok(url) = &maybe_url
+        && is_yabu_scheme(url.scheme())
+    {
         return maybe_url;
     }
 
+    // Absolute paths are almost certainly sockets
+    if s.starts_with('/') {
+        return Url::parse(&format!("{UNIX_URL_SCHEME}://{s}")).map_err(|err| err.to_string());
+    }
+
     // Chain from `maybe_url` to preserve the original error
     maybe_url = maybe_url
         // Try to guess what the user meant. Try adding the url scheme and a port.
@@ -65,11 +409,12 @@ pub fn url_from_str(s: &str) -> Result<Url, String> {
     // We're finished with recovery; raise any errors now
     let url = maybe_url?;
 
-    if url.scheme() == URL_SCHEME {
+    if is_yabu_scheme(url.scheme()) {
         Ok(url)
     } else {
         Err(format!(
-            "server URL has an incorrect scheme (expected {URL_SCHEME}, got {})",
+            "server URL has an incorrect scheme \
+            (expected {URL_SCHEME}, {TLS_URL_SCHEME}, or {UNIX_URL_SCHEME}, got {})",
             url.scheme()
         ))
     }
@@ -1,17 +1,23 @@
 #![feature(derive_default_enum, let_chains, once_cell)]
 
+pub mod codec;
 pub mod connection;
+pub mod list;
+pub mod recurrence;
+pub mod tls;
 
 use num_derive::{FromPrimitive, ToPrimitive};
 use serde::{Deserialize, Serialize};
 use serde_json::Error as SerdeJsonError;
 use std::{
     borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
     fmt::{self, Display, Formatter},
     io::Error as IoError,
     lazy::SyncLazy,
     mem,
     num::{NonZeroU32, ParseIntError, TryFromIntError},
+    path::PathBuf,
     str::FromStr,
 };
 use thiserror::Error;
@@ -19,11 +25,24 @@ use time::{
     format_description::{self, FormatItem},
     OffsetDateTime,
 };
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use url::Url;
 
+use crate::{
+    list::{List, ListDelta, ListId},
+    recurrence::Recurrence,
+};
+
 pub const DEFAULT_SERVER_PORT: u16 = 11180;
 pub const URL_SCHEME: &str = "yabu";
+/// Like `URL_SCHEME`, but the connection is wrapped in TLS.
+pub const TLS_URL_SCHEME: &str = "yabus";
+/// The path of the URL is the path to a Unix domain socket,
+/// as in `yabu+unix:///run/yabuserver.sock`.
+pub const UNIX_URL_SCHEME: &str = "yabu+unix";
+
+/// Must be bumped whenever `Message`, `Response`, or anything they contain
+/// changes shape. Clients and servers refuse to talk across versions.
+pub const PROTOCOL_VERSION: u32 = 14;
 
 pub static DATE_TIME_FORMAT: SyncLazy<Vec<FormatItem>> = SyncLazy::new(|| {
     format_description::parse(
@@ -42,6 +61,14 @@ pub enum YabuError {
     TaskDoesntExist(TaskId),
     #[error("unknown priority {0}")]
     UnknownPriority(String),
+    #[error("unknown sort key {0} (expected `id`, `due`, or `priority`)")]
+    UnknownSortKey(String),
+    #[error("invalid tag `{0}` (tags must be non-empty and can't contain whitespace or commas, or start with `+` or `-`)")]
+    InvalidTag(String),
+    #[error("invalid repeat rule `{0}` (expected something like `day`, `2 weeks`, or `week on mon,thu`)")]
+    InvalidRecurrence(String),
+    #[error("invalid list name `{0}` (list names must be non-empty, can't start or end with whitespace, and can't start with `-`)")]
+    InvalidListName(String),
     #[error("io error")]
     IoError(#[from] IoError),
     #[error("error while serializing a value")]
@@ -50,6 +77,34 @@ pub enum YabuError {
     UrlHasNoHost(Url),
     #[error("dns lookup for `{0}` returned no addresses")]
     DnsLookupFailed(Url),
+    #[error("frame of {size} bytes exceeds the maximum frame size ({max} bytes)")]
+    FrameTooLarge { size: usize, max: u32 },
+    #[error("unsupported framing version {0}")]
+    UnsupportedFraming(u8),
+    #[error("server speaks protocol version {server}, but this client speaks version {client}")]
+    IncompatibleProtocol { client: u32, server: u32 },
+    #[error("tls error")]
+    TlsError(#[from] tokio_rustls::rustls::Error),
+    #[error("`{0}` does not contain any PEM certificates")]
+    NoCertificates(PathBuf),
+    #[error("`{0}` contains an invalid certificate")]
+    InvalidCertificate(PathBuf),
+    #[error("`{0}` is not a valid server name for TLS")]
+    InvalidServerName(String),
+    #[error("server url ({0}) does not contain a valid socket path")]
+    InvalidSocketPath(Url),
+    #[error("unix domain sockets aren't supported on this platform")]
+    UnixSocketsUnsupported,
+    #[error("the server returned an error")]
+    RpcError(#[from] YabuRpcError),
+    #[error("unexpected response from the server: {0:?}")]
+    UnexpectedResponse(Box<Response>),
+    /// The request might have reached the server before the connection closed.
+    #[error("the connection to the server was closed")]
+    ConnectionClosed,
+    /// The connection was already closed, so the request never reached the server.
+    #[error("the connection to the server was closed before the request could be sent")]
+    NotSent,
 }
 
 #[derive(Clone, Debug, Deserialize, Error, Serialize)]
@@ -58,6 +113,31 @@ pub enum YabuRpcError {
     TaskDoesntExist(TaskId),
     #[error("unknown priority {0}")]
     UnknownPriority(String),
+    #[error("invalid tag `{0}`")]
+    InvalidTag(String),
+    #[error("not authenticated (is your API token correct?)")]
+    Unauthorized,
+    #[error("task {task} can't be a subtask of task {parent}, since that would make a cycle")]
+    SubtaskCycle { task: TaskId, parent: TaskId },
+    #[error("task {task} can't depend on task {dependency}, since that would make a cycle")]
+    DependencyCycle { task: TaskId, dependency: TaskId },
+    #[error("list {0} does not exist")]
+    ListDoesntExist(ListId),
+    #[error("there's already a list named `{0}`")]
+    ListNameTaken(String),
+    #[error("invalid list name `{0}`")]
+    InvalidListName(String),
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    /// Something went wrong on the server, such as a database error.
+    /// Details are logged by the server.
+    #[error("internal server error: {message}")]
+    Internal { message: String },
+    #[error("message {index} in the batch failed, so none of it was applied: {error}")]
+    BatchFailed {
+        index: usize,
+        error: Box<YabuRpcError>,
+    },
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -87,7 +167,18 @@ impl TryFrom<u32> for TaskId {
 }
 
 #[derive(
-    Clone, Copy, Debug, Default, Deserialize, Eq, FromPrimitive, PartialEq, Serialize, ToPrimitive,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    Deserialize,
+    Eq,
+    FromPrimitive,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    Serialize,
+    ToPrimitive,
 )]
 pub enum Priority {
     Lowest,
@@ -99,6 +190,15 @@ pub enum Priority {
 }
 
 impl Priority {
+    /// Every priority, from lowest to highest.
+    pub const ALL: [Self; 5] = [
+        Self::Lowest,
+        Self::Low,
+        Self::Medium,
+        Self::High,
+        Self::Critical,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         match self {
             Priority::Lowest => "lowest",
@@ -133,6 +233,19 @@ impl FromStr for Priority {
     }
 }
 
+/// Checks that a tag can be safely displayed and typed on the command line.
+pub fn validate_tag(tag: &str) -> Result<(), YabuError> {
+    let valid = !tag.is_empty()
+        && !tag.starts_with(['+', '-'])
+        && !tag.contains(|c: char| c.is_whitespace() || c == ',');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(YabuError::InvalidTag(tag.to_string()))
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Task {
     pub id: Option<TaskId>,
@@ -140,6 +253,30 @@ pub struct Task {
     pub description: Cow<'static, str>,
     pub priority: Priority,
     pub due_date: Option<OffsetDateTime>,
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+    /// The task this is a subtask of.
+    #[serde(default)]
+    pub parent: Option<TaskId>,
+    /// Filled in by the server for tasks with subtasks.
+    /// Ignored when adding or updating a task.
+    #[serde(default)]
+    pub progress: Option<Progress>,
+    /// Tasks which have to be completed before this one can be started.
+    #[serde(default)]
+    pub depends_on: BTreeSet<TaskId>,
+    /// Filled in by the server: the tasks in `depends_on` which aren't
+    /// complete yet. Ignored when adding or updating a task.
+    #[serde(default)]
+    pub blocked_by: BTreeSet<TaskId>,
+    /// The list this task belongs to, if any.
+    #[serde(default)]
+    pub list: Option<ListId>,
+    /// Longer notes about the task, in Markdown.
+    #[serde(default)]
+    pub notes: Option<String>,
 }
 
 impl Task {
@@ -156,6 +293,14 @@ impl Task {
             description: description.into(),
             priority,
             due_date,
+            tags: BTreeSet::new(),
+            recurrence: None,
+            parent: None,
+            progress: None,
+            depends_on: BTreeSet::new(),
+            blocked_by: BTreeSet::new(),
+            list: None,
+            notes: None,
         }
     }
 
@@ -163,6 +308,22 @@ impl Task {
         self.id.ok_or(YabuError::TaskHasNoId)
     }
 
+    /// The task to add once this one is completed, if it repeats. Tasks
+    /// without a due date repeat from `now`.
+    pub fn next_occurrence(&self, now: OffsetDateTime) -> Option<Task> {
+        let recurrence = self.recurrence.as_ref()?;
+        let (due_date, recurrence) = recurrence.next(self.due_date.unwrap_or(now))?;
+
+        Some(Task {
+            id: None,
+            complete: false,
+            due_date: Some(due_date),
+            recurrence: Some(recurrence),
+            progress: None,
+            ..self.clone()
+        })
+    }
+
     pub fn apply_delta(&mut self, delta: TaskDelta) {
         // trying to use `self.description` directly fails borrowcheck
         let mut description = Cow::Borrowed("");
@@ -172,7 +333,74 @@ impl Task {
         self.description = delta.description.apply_to(description);
         self.priority = delta.priority.apply_to(self.priority);
         self.due_date = delta.due_date.apply_to(self.due_date);
+        self.recurrence = delta.recurrence.apply_to(self.recurrence.take());
+        self.parent = delta.parent.apply_to(self.parent);
+        self.list = delta.list.apply_to(self.list);
+        self.notes = delta.notes.apply_to(self.notes.take());
+
+        for tag_delta in delta.tags {
+            tag_delta.apply_to(&mut self.tags);
+        }
+
+        for dependency_delta in delta.depends_on {
+            dependency_delta.apply_to(&mut self.depends_on);
+        }
+    }
+}
+
+/// How many of a task's subtasks are complete. Only direct subtasks count.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Progress {
+    pub complete: u32,
+    pub total: u32,
+}
+
+impl Display for Progress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(f, "{}/{}", self.complete, self.total)
+    }
+}
+
+/// Orders tasks so that subtasks come straight after their parent, pairing
+/// each with how deeply it's nested. Tasks whose parent isn't in `tasks`
+/// are treated as top-level. Otherwise, the order of `tasks` is kept.
+pub fn nest_subtasks(tasks: Vec<Task>) -> Vec<(usize, Task)> {
+    let ids = tasks
+        .iter()
+        .filter_map(|task| task.id)
+        .collect::<BTreeSet<_>>();
+    let mut children = BTreeMap::<TaskId, Vec<Task>>::new();
+    let mut top_level = Vec::new();
+
+    for task in tasks {
+        match task.parent {
+            Some(parent) if ids.contains(&parent) => children.entry(parent).or_default().push(task),
+            _ => top_level.push(task),
+        }
+    }
+
+    let mut res = Vec::new();
+    let mut stack = top_level
+        .into_iter()
+        .rev()
+        .map(|task| (0, task))
+        .collect::<Vec<_>>();
+
+    while let Some((depth, task)) = stack.pop() {
+        let subtasks = task
+            .id
+            .and_then(|id| children.remove(&id))
+            .unwrap_or_default();
+        stack.extend(
+            subtasks
+                .into_iter()
+                .rev()
+                .map(|subtask| (depth + 1, subtask)),
+        );
+        res.push((depth, task));
     }
+
+    res
 }
 
 #[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
@@ -192,87 +420,295 @@ impl<T> Delta<T> {
     }
 }
 
+/// A change to a set, such as a task's tags. Unlike `Delta`, this can
+/// describe adding or removing a single element.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum SetDelta<T: Ord> {
+    Add(T),
+    Remove(T),
+    Replace(BTreeSet<T>),
+}
+
+impl<T: Ord> SetDelta<T> {
+    pub fn apply_to(self, set: &mut BTreeSet<T>) {
+        match self {
+            SetDelta::Add(value) => {
+                set.insert(value);
+            }
+
+            SetDelta::Remove(value) => {
+                set.remove(&value);
+            }
+
+            SetDelta::Replace(new) => *set = new,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct TaskDelta {
+    #[serde(default)]
     pub complete: Delta<bool>,
+    #[serde(default)]
     pub description: Delta<Cow<'static, str>>,
+    #[serde(default)]
     pub priority: Delta<Priority>,
+    #[serde(default)]
     pub due_date: Delta<Option<OffsetDateTime>>,
+    /// Applied in order.
+    #[serde(default)]
+    pub tags: Vec<SetDelta<String>>,
+    #[serde(default)]
+    pub recurrence: Delta<Option<Recurrence>>,
+    #[serde(default)]
+    pub parent: Delta<Option<TaskId>>,
+    /// Applied in order.
+    #[serde(default)]
+    pub depends_on: Vec<SetDelta<TaskId>>,
+    #[serde(default)]
+    pub list: Delta<Option<ListId>>,
+    #[serde(default)]
+    pub notes: Delta<Option<String>>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SortKey {
+    #[default]
+    Id,
+    /// Tasks without a due date are always sorted last.
+    DueDate,
+    Priority,
+}
+
+impl SortKey {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortKey::Id => "id",
+            SortKey::DueDate => "due",
+            SortKey::Priority => "priority",
+        }
+    }
+}
+
+impl Display for SortKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        self.as_str().fmt(f)
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = YabuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "id" => Ok(Self::Id),
+            "due" | "due_date" | "due-date" => Ok(Self::DueDate),
+            "priority" => Ok(Self::Priority),
+            _ => Err(YabuError::UnknownSortKey(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Sort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+/// Describes which tasks to fetch and in which order. The default query
+/// returns every task, sorted by id.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TaskQuery {
+    /// `Some(true)` for only complete tasks, `Some(false)` for only incomplete ones.
+    pub complete: Option<bool>,
+    pub min_priority: Option<Priority>,
+    pub max_priority: Option<Priority>,
+    pub due_before: Option<OffsetDateTime>,
+    pub due_after: Option<OffsetDateTime>,
+    /// Only incomplete tasks whose due date has passed.
+    pub overdue: bool,
+    /// Only tasks whose description contains this text, ignoring ASCII case.
+    pub text: Option<String>,
+    /// Only tasks which have all of these tags.
+    pub tags: BTreeSet<String>,
+    /// Only incomplete tasks which aren't waiting on any others.
+    pub ready: bool,
+    /// Only tasks in this list.
+    pub list: Option<ListId>,
+    /// Applied in order. Ties are always broken by task id.
+    pub sort: Vec<Sort>,
+    pub limit: Option<u32>,
+    pub offset: u32,
+}
+
+impl TaskQuery {
+    /// Returns `true` if this query might leave out some tasks
+    /// (as opposed to only sorting them).
+    pub fn has_filters(&self) -> bool {
+        self.complete.is_some()
+            || self.min_priority.is_some()
+            || self.max_priority.is_some()
+            || self.due_before.is_some()
+            || self.due_after.is_some()
+            || self.overdue
+            || self.text.is_some()
+            || !self.tags.is_empty()
+            || self.ready
+            || self.list.is_some()
+            || self.limit.is_some()
+            || self.offset > 0
+    }
+
+    /// Whether `task` passes this query's filters.
+    /// Sorting and pagination are ignored.
+    pub fn matches(&self, task: &Task) -> bool {
+        let due_before = |date| matches!(task.due_date, Some(due_date) if due_date < date);
+        let due_after = |date| matches!(task.due_date, Some(due_date) if due_date > date);
+        let has_text = |text: &String| {
+            (task.description.to_ascii_lowercase()).contains(&text.to_ascii_lowercase())
+        };
+
+        self.complete
+            .iter()
+            .all(|&complete| task.complete == complete)
+            && self.min_priority.iter().all(|&min| task.priority >= min)
+            && self.max_priority.iter().all(|&max| task.priority <= max)
+            && self.due_before.into_iter().all(due_before)
+            && self.due_after.into_iter().all(due_after)
+            && (!self.overdue || (!task.complete && due_before(OffsetDateTime::now_utc())))
+            && self.text.iter().all(has_text)
+            && self.tags.is_subset(&task.tags)
+            && (!self.ready || (!task.complete && task.blocked_by.is_empty()))
+            && self.list.iter().all(|&list| task.list == Some(list))
+    }
+}
+
+/// Optional protocol features. Both sides advertise what they support during
+/// the handshake, and only features supported by both may be used.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum Capability {
+    /// A capability only known to a newer peer.
+    #[serde(other)]
+    Unknown,
+}
+
+// `Hello` and `HelloResponse` are exchanged before protocol versions are
+// checked, so they must stay readable by every version. New fields need
+// `#[serde(default)]`.
+
+/// Sent by the client once framing has been established.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub client_name: Cow<'static, str>,
+    pub capabilities: BTreeSet<Capability>,
+    /// An API token issued by the server. Every message sent without a valid
+    /// token is answered with `YabuRpcError::Unauthorized`.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum HelloResponse {
+    Welcome {
+        protocol_version: u32,
+        /// The capabilities supported by both the client and the server.
+        capabilities: BTreeSet<Capability>,
+        max_frame_size: u32,
+        /// Whether the client's token was accepted.
+        #[serde(default)]
+        authenticated: bool,
+    },
+    Incompatible {
+        protocol_version: u32,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Message {
+    /// Answered with the task as it was stored, including its new ID.
     Add(Task),
+    /// Answered with `Response::Task`.
+    Get(TaskId),
+    /// Equivalent to `Message::Query(TaskQuery::default())`.
+    /// Kept for older clients.
     List,
+    Query(TaskQuery),
+    /// Answered with the task as it is after the update.
     Update(TaskId, TaskDelta),
     Remove(TaskId),
+    /// Asks to be told about changes to the user's tasks which match the query.
+    /// Sorting and pagination are ignored. The server answers with
+    /// `Response::Nothing`, then sends a `ServerFrame::Event` for every change,
+    /// using this request's ID as the subscription ID.
+    ///
+    /// Tasks which stop matching the query are still reported once,
+    /// so that subscribers know to forget them.
+    Subscribe(TaskQuery),
+    /// Ends the subscription with the given ID. Always answered
+    /// with `Response::Nothing`.
+    Unsubscribe(RequestId),
+    /// Handles each message in order, answering with a `Response::Batch`
+    /// of their responses. The whole batch is committed at once.
+    /// Subscriptions can't be started or ended from a batch.
+    ///
+    /// If `atomic` is set, the batch stops at the first message answered
+    /// with an error, nothing in it is applied, and the batch is answered
+    /// with `YabuRpcError::BatchFailed` instead.
+    Batch {
+        messages: Vec<Message>,
+        atomic: bool,
+    },
+    /// Answered with the list as it was stored, including its new ID.
+    AddList(List),
+    /// Answered with `Response::Lists`, including archived lists.
+    Lists,
+    /// Answered with the list as it is after the update.
+    UpdateList(ListId, ListDelta),
+    /// Removes the list along with every task in it.
+    RemoveList(ListId),
 }
 
-impl Message {
-    pub async fn read_from_socket<R: AsyncReadExt + Unpin>(
-        mut socket: R,
-    ) -> Result<Self, YabuError> {
-        // read the payload length
-        let mut length_buf = [0u8; 2];
-        socket.read_exact(&mut length_buf).await?;
-        let length = usize::from(u16::from_le_bytes(length_buf));
-
-        // now for the payload
-        let mut buf = vec![0; length];
-        socket.read_exact(&mut buf).await?;
-        Ok(serde_json::from_slice::<Self>(&buf)?)
-    }
-
-    pub async fn write_to_socket<W: AsyncWriteExt + Unpin>(
-        &self,
-        mut socket: W,
-    ) -> Result<(), YabuError> {
-        let buffer = serde_json::to_vec(self)?;
+/// Chosen by the client to match up responses with their requests.
+pub type RequestId = u32;
 
-        // first, write out the payload length
-        let length_bytes = u16::try_from(buffer.len())?.to_le_bytes();
-        socket.write_all(&length_bytes).await?;
+/// Wraps every message sent by a client once the handshake is done.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Request {
+    pub id: RequestId,
+    pub message: Message,
+}
 
-        // ...then write the payload
-        socket.write_all(&buffer).await?;
-        Ok(())
-    }
+/// Everything the server sends once the handshake is done.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ServerFrame {
+    /// Answers the request with the same ID.
+    Response { id: RequestId, response: Response },
+    /// A change to a task, sent to the subscription started
+    /// by the request with this ID.
+    Event {
+        subscription: RequestId,
+        event: TaskEvent,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Response {
     Nothing,
+    Task(Task),
     Tasks(Vec<Task>),
     Error(YabuRpcError),
+    /// Answers `Message::Batch`, in the same order as its messages.
+    Batch(Vec<Response>),
+    List(List),
+    Lists(Vec<List>),
 }
 
-impl Response {
-    pub async fn read_from_socket<R: AsyncReadExt + Unpin>(
-        mut socket: R,
-    ) -> Result<Self, YabuError> {
-        // read the payload length
-        let mut length_buf = [0u8; 2];
-        socket.read_exact(&mut length_buf).await?;
-        let length = usize::from(u16::from_le_bytes(length_buf));
-
-        // now for the payload
-        let mut buf = vec![0; length];
-        socket.read_exact(&mut buf).await?;
-        Ok(serde_json::from_slice::<Self>(&buf)?)
-    }
-
-    pub async fn write_to_socket<W: AsyncWriteExt + Unpin>(
-        &self,
-        mut socket: W,
-    ) -> Result<(), YabuError> {
-        let buffer = serde_json::to_vec(self)?;
-
-        // first, write out the payload length
-        let length_bytes = u16::try_from(buffer.len())?.to_le_bytes();
-        socket.write_all(&length_bytes).await?;
-
-        // ...then write the payload
-        socket.write_all(&buffer).await?;
-        Ok(())
-    }
+/// A change to one of a user's tasks, sent to subscriptions.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TaskEvent {
+    Added(Task),
+    Updated(Task),
+    Removed(TaskId),
 }
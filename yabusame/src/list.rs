@@ -0,0 +1,83 @@
+//! Named lists which tasks can be sorted into, such as one per project.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    mem,
+    num::{NonZeroU32, ParseIntError},
+    str::FromStr,
+};
+
+use crate::{Delta, YabuError};
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[repr(transparent)]
+pub struct ListId(pub NonZeroU32);
+
+impl Display for ListId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ListId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(NonZeroU32::from_str(s)?))
+    }
+}
+
+impl TryFrom<u32> for ListId {
+    type Error = YabuError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Ok(Self(NonZeroU32::try_from(value)?))
+    }
+}
+
+/// Each of a user's lists has a different name.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct List {
+    pub id: Option<ListId>,
+    pub name: String,
+    /// Archived lists keep their tasks, but clients don't offer them
+    /// unless asked.
+    #[serde(default)]
+    pub archived: bool,
+}
+
+impl List {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            id: None,
+            name: name.into(),
+            archived: false,
+        }
+    }
+
+    pub fn apply_delta(&mut self, delta: ListDelta) {
+        self.name = delta.name.apply_to(mem::take(&mut self.name));
+        self.archived = delta.archived.apply_to(self.archived);
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ListDelta {
+    #[serde(default)]
+    pub name: Delta<String>,
+    #[serde(default)]
+    pub archived: Delta<bool>,
+}
+
+/// Checks that a list's name can be typed on the command line, where `-`
+/// stands for no list at all.
+pub fn validate_list_name(name: &str) -> Result<(), YabuError> {
+    let valid = !name.is_empty() && name.trim() == name && !name.starts_with('-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(YabuError::InvalidListName(name.to_string()))
+    }
+}
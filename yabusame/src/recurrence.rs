@@ -0,0 +1,389 @@
+//! Rules for tasks which repeat, loosely modelled on iCalendar's `RRULE`.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    num::NonZeroU32,
+    str::FromStr,
+};
+use time::{Date, Duration, Month, OffsetDateTime, Weekday};
+
+use crate::YabuError;
+
+/// The longest a rule can go between occurrences, in days, weeks, or months.
+/// Anything longer is almost certainly a mistake, and would eventually
+/// overflow the due date.
+pub const MAX_INTERVAL: u32 = 1000;
+
+const WEEKDAYS: [(Weekday, &str); 7] = [
+    (Weekday::Monday, "monday"),
+    (Weekday::Tuesday, "tuesday"),
+    (Weekday::Wednesday, "wednesday"),
+    (Weekday::Thursday, "thursday"),
+    (Weekday::Friday, "friday"),
+    (Weekday::Saturday, "saturday"),
+    (Weekday::Sunday, "sunday"),
+];
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    /// On the same day of the month as the task was due, or the last day
+    /// of the month for months which are too short. Occurrences after that
+    /// keep the earlier day, since each is only due relative to the last.
+    Monthly,
+}
+
+impl Frequency {
+    fn unit(self) -> &'static str {
+        match self {
+            Frequency::Daily => "day",
+            Frequency::Weekly => "week",
+            Frequency::Monthly => "month",
+        }
+    }
+}
+
+/// How often a task repeats. Once a repeating task is completed, the server
+/// adds its next occurrence, which takes the rule over from it.
+///
+/// Written as `[interval] unit [on weekdays]`, such as `day`, `2 weeks`,
+/// or `week on mon,thu`. `until` and `count` aren't part of that syntax.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    /// Repeats every `interval` days, weeks, or months.
+    /// At most `MAX_INTERVAL`.
+    pub interval: NonZeroU32,
+    /// Only for `Frequency::Weekly`. The days of the week to repeat on,
+    /// from Monday to Sunday. If empty, the task repeats on the weekday
+    /// it was due.
+    #[serde(default)]
+    pub weekdays: Vec<Weekday>,
+    /// No occurrences are added which would be due after this.
+    #[serde(default)]
+    pub until: Option<OffsetDateTime>,
+    /// How many occurrences are left, counting the current one.
+    #[serde(default)]
+    pub count: Option<u32>,
+}
+
+impl Recurrence {
+    pub fn new(frequency: Frequency) -> Self {
+        Self {
+            frequency,
+            interval: NonZeroU32::new(1).unwrap(),
+            weekdays: Vec::new(),
+            until: None,
+            count: None,
+        }
+    }
+
+    /// Returns when the occurrence after the one due at `due_date` is due,
+    /// along with the rule it should repeat with, or `None` if the rule
+    /// has run out or the next due date would be out of range.
+    pub fn next(&self, due_date: OffsetDateTime) -> Option<(OffsetDateTime, Self)> {
+        let count = match self.count {
+            Some(0 | 1) => return None,
+            count => count.map(|count| count - 1),
+        };
+
+        let interval = self.interval.get();
+
+        let next_due_date = match self.frequency {
+            Frequency::Daily => due_date.checked_add(Duration::days(interval.into()))?,
+
+            Frequency::Weekly => {
+                let today = due_date.weekday().number_days_from_monday();
+                let days = self
+                    .weekdays
+                    .iter()
+                    .map(|day| day.number_days_from_monday());
+
+                match days.clone().filter(|&day| day > today).min() {
+                    // Later the same week
+                    Some(day) => due_date.checked_add(Duration::days((day - today).into()))?,
+
+                    None => {
+                        let first = days.min().unwrap_or(today);
+                        let week_start = due_date.checked_sub(Duration::days(today.into()))?;
+                        let weeks = Duration::weeks(interval.into());
+                        let offset = weeks.checked_add(Duration::days(first.into()))?;
+                        week_start.checked_add(offset)?
+                    }
+                }
+            }
+
+            Frequency::Monthly => due_date.replace_date(add_months(due_date.date(), interval)?),
+        };
+
+        if matches!(self.until, Some(until) if next_due_date > until) {
+            return None;
+        }
+
+        let next = Self {
+            count,
+            ..self.clone()
+        };
+
+        Some((next_due_date, next))
+    }
+}
+
+/// Clamps the day to the end of the month, so January 31st plus a month
+/// is the last day of February.
+fn add_months(date: Date, months: u32) -> Option<Date> {
+    let month_index = date.year() * 12 + i32::from(u8::from(date.month())) - 1;
+    let month_index = month_index.checked_add(i32::try_from(months).ok()?)?;
+
+    let year = month_index.div_euclid(12);
+    let month = Month::try_from(u8::try_from(month_index.rem_euclid(12) + 1).ok()?).ok()?;
+
+    (1..=date.day())
+        .rev()
+        .find_map(|day| Date::from_calendar_date(year, month, day).ok())
+}
+
+/// Accepts any abbreviation at least three letters long, such as `thu` or `thurs`.
+fn weekday_from_str(s: &str) -> Option<Weekday> {
+    WEEKDAYS
+        .iter()
+        .find(|(_, name)| s.len() >= 3 && name.starts_with(s))
+        .map(|&(weekday, _)| weekday)
+}
+
+/// The three letter abbreviation.
+fn weekday_name(weekday: Weekday) -> &'static str {
+    let (_, name) = WEEKDAYS[usize::from(weekday.number_days_from_monday())];
+    &name[..3]
+}
+
+impl Display for Recurrence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), fmt::Error> {
+        let unit = self.frequency.unit();
+
+        match self.interval.get() {
+            1 => write!(f, "{unit}")?,
+            interval => write!(f, "{interval} {unit}s")?,
+        }
+
+        if !self.weekdays.is_empty() {
+            let weekdays = self.weekdays.iter().map(|&day| weekday_name(day));
+            write!(f, " on {}", weekdays.collect::<Vec<_>>().join(","))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = YabuError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || YabuError::InvalidRecurrence(s.to_string());
+
+        let lowercase = s.to_ascii_lowercase();
+        let mut words = lowercase
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .peekable();
+
+        // Reads naturally after `--every`, but might be typed anyway
+        words.next_if_eq(&"every");
+
+        let interval = match words.peek().map(|word| NonZeroU32::from_str(word)) {
+            Some(Ok(interval)) => {
+                words.next();
+                interval
+            }
+
+            Some(Err(_)) => NonZeroU32::new(1).unwrap(),
+            None => return Err(invalid()),
+        };
+
+        if interval.get() > MAX_INTERVAL {
+            return Err(invalid());
+        }
+
+        let frequency = match words.next().ok_or_else(invalid)? {
+            "day" | "days" | "daily" => Frequency::Daily,
+            "week" | "weeks" | "weekly" => Frequency::Weekly,
+            "month" | "months" | "monthly" => Frequency::Monthly,
+            _ => return Err(invalid()),
+        };
+
+        let mut weekdays = Vec::new();
+
+        if words.next_if_eq(&"on").is_some() {
+            if frequency != Frequency::Weekly {
+                return Err(invalid());
+            }
+
+            for word in words.by_ref() {
+                weekdays.push(weekday_from_str(word).ok_or_else(invalid)?);
+            }
+
+            if weekdays.is_empty() {
+                return Err(invalid());
+            }
+
+            weekdays.sort_by_key(|day| day.number_days_from_monday());
+            weekdays.dedup();
+        }
+
+        if words.next().is_some() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            interval,
+            weekdays,
+            ..Self::new(frequency)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: Month, day: u8) -> OffsetDateTime {
+        Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .midnight()
+            .assume_utc()
+    }
+
+    fn rule(s: &str) -> Recurrence {
+        Recurrence::from_str(s).unwrap()
+    }
+
+    fn next_due(recurrence: &Recurrence, due_date: OffsetDateTime) -> Option<OffsetDateTime> {
+        recurrence.next(due_date).map(|(due_date, _)| due_date)
+    }
+
+    #[test]
+    fn add_months_clamps_to_the_end_of_the_month() {
+        let jan_31 = Date::from_calendar_date(2023, Month::January, 31).unwrap();
+        let leap_jan_31 = Date::from_calendar_date(2024, Month::January, 31).unwrap();
+
+        assert_eq!(
+            add_months(jan_31, 1),
+            Date::from_calendar_date(2023, Month::February, 28).ok()
+        );
+        assert_eq!(
+            add_months(leap_jan_31, 1),
+            Date::from_calendar_date(2024, Month::February, 29).ok()
+        );
+        assert_eq!(
+            add_months(jan_31, 12),
+            Date::from_calendar_date(2024, Month::January, 31).ok()
+        );
+    }
+
+    #[test]
+    fn monthly_from_the_31st() {
+        let monthly = rule("month");
+
+        assert_eq!(
+            next_due(&monthly, date(2023, Month::January, 31)),
+            Some(date(2023, Month::February, 28))
+        );
+        assert_eq!(
+            next_due(&monthly, date(2024, Month::January, 31)),
+            Some(date(2024, Month::February, 29))
+        );
+    }
+
+    #[test]
+    fn daily_with_an_interval() {
+        assert_eq!(
+            next_due(&rule("3 days"), date(2024, Month::February, 27)),
+            Some(date(2024, Month::March, 1))
+        );
+    }
+
+    #[test]
+    fn huge_intervals_run_out_instead_of_overflowing() {
+        let due_date = date(2024, Month::January, 1);
+
+        for frequency in [Frequency::Daily, Frequency::Weekly, Frequency::Monthly] {
+            let recurrence = Recurrence {
+                interval: NonZeroU32::new(u32::MAX).unwrap(),
+                ..Recurrence::new(frequency)
+            };
+            assert_eq!(next_due(&recurrence, due_date), None, "{frequency:?}");
+        }
+    }
+
+    #[test]
+    fn huge_intervals_dont_parse() {
+        assert_eq!(rule("1000 days").interval.get(), MAX_INTERVAL);
+        assert!(Recurrence::from_str("1001 weeks").is_err());
+    }
+
+    #[test]
+    fn weekly_on_weekdays() {
+        // 2024-01-01 is a Monday
+        let monday = date(2024, Month::January, 1);
+        let thursday = date(2024, Month::January, 4);
+
+        let weekly = rule("week on thu,mon");
+        assert_eq!(weekly.weekdays, [Weekday::Monday, Weekday::Thursday]);
+        assert_eq!(next_due(&weekly, monday), Some(thursday));
+        assert_eq!(
+            next_due(&weekly, thursday),
+            Some(date(2024, Month::January, 8))
+        );
+
+        // Skips a week after the last weekday
+        assert_eq!(
+            next_due(&rule("2 weeks on mon,thu"), thursday),
+            Some(date(2024, Month::January, 15))
+        );
+
+        // Without weekdays, the weekday the task was due on
+        assert_eq!(
+            next_due(&rule("week"), thursday),
+            Some(date(2024, Month::January, 11))
+        );
+    }
+
+    #[test]
+    fn count_runs_out() {
+        let due_date = date(2024, Month::January, 1);
+        let mut recurrence = Recurrence {
+            count: Some(2),
+            ..rule("day")
+        };
+
+        let (_, next) = recurrence.next(due_date).unwrap();
+        assert_eq!(next.count, Some(1));
+        assert!(next.next(due_date).is_none());
+
+        recurrence.count = Some(0);
+        assert!(recurrence.next(due_date).is_none());
+    }
+
+    #[test]
+    fn until_runs_out() {
+        let recurrence = Recurrence {
+            until: Some(date(2024, Month::January, 2)),
+            ..rule("day")
+        };
+
+        assert_eq!(
+            next_due(&recurrence, date(2024, Month::January, 1)),
+            Some(date(2024, Month::January, 2))
+        );
+        assert!(recurrence.next(date(2024, Month::January, 2)).is_none());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for s in ["day", "3 days", "week on mon,thu", "2 months"] {
+            assert_eq!(rule(s).to_string(), s);
+        }
+    }
+}
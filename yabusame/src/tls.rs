@@ -0,0 +1,45 @@
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use tokio_rustls::rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore};
+
+use crate::YabuError;
+
+/// Reads every certificate in a PEM file.
+pub fn load_certificates(path: &Path) -> Result<Vec<Certificate>, YabuError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certificates = rustls_pemfile::certs(&mut reader)?;
+
+    if certificates.is_empty() {
+        return Err(YabuError::NoCertificates(path.to_owned()));
+    }
+
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+/// Trusts the usual web PKI roots, plus every certificate in `ca_file`
+/// (useful for self-signed certificates).
+pub fn client_config(ca_file: Option<&Path>) -> Result<Arc<ClientConfig>, YabuError> {
+    let mut roots = RootCertStore::empty();
+
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+
+    if let Some(ca_file) = ca_file {
+        for certificate in load_certificates(ca_file)? {
+            roots
+                .add(&certificate)
+                .map_err(|_| YabuError::InvalidCertificate(ca_file.to_owned()))?;
+        }
+    }
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(Arc::new(config))
+}
@@ -0,0 +1,167 @@
+use argh::FromArgs;
+use std::{net::IpAddr, path::PathBuf};
+use yabusame::{codec::DEFAULT_MAX_FRAME_SIZE, DEFAULT_SERVER_PORT};
+
+use crate::db::DEFAULT_DATABASE_URL;
+
+/// The server behind the Yabusame todo list.
+#[derive(Debug, FromArgs)]
+pub struct Args {
+    #[argh(
+        option,
+        short = 'a',
+        description = "address to listen on",
+        default = "[0, 0, 0, 0].into()"
+    )]
+    pub listen_address: IpAddr,
+
+    #[argh(
+        option,
+        short = 'p',
+        description = "port to serve on",
+        default = "DEFAULT_SERVER_PORT"
+    )]
+    pub port: u16,
+
+    #[argh(
+        option,
+        description = "listen only on this unix domain socket, and not over TCP at all"
+    )]
+    pub unix_socket: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "largest message to accept from clients, in bytes",
+        default = "DEFAULT_MAX_FRAME_SIZE"
+    )]
+    pub max_frame_size: u32,
+
+    #[argh(
+        option,
+        short = 'd',
+        description = "path to the database (use `:memory:` for a temporary database)",
+        default = "DEFAULT_DATABASE_URL.to_string()"
+    )]
+    pub database: String,
+
+    #[argh(
+        option,
+        description = "PEM file with the certificate chain to serve `yabus://` with"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    #[argh(option, description = "PEM file with the private key for `--tls-cert`")]
+    pub tls_key: Option<PathBuf>,
+
+    #[argh(subcommand)]
+    pub subcommand: Option<Subcommand>,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+pub enum Subcommand {
+    Migrate(Migrate),
+    Token(TokenCommand),
+    User(UserCommand),
+}
+
+/// Bring the database schema up to date without starting the server.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "migrate")]
+pub struct Migrate {
+    #[argh(
+        switch,
+        description = "only list the migrations which would be applied"
+    )]
+    pub dry_run: bool,
+}
+
+/// Manage the API tokens clients use to authenticate.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "token")]
+pub struct TokenCommand {
+    #[argh(subcommand)]
+    pub action: TokenAction,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+pub enum TokenAction {
+    Create(CreateToken),
+    Revoke(RevokeToken),
+    List(ListTokens),
+}
+
+/// Create a new API token and print it.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "create")]
+pub struct CreateToken {
+    #[argh(positional, description = "a name to remember this token by")]
+    pub name: String,
+
+    #[argh(
+        option,
+        short = 'u',
+        description = "the user this token authenticates as"
+    )]
+    pub user: String,
+}
+
+/// Revoke an API token. Clients using it are locked out once they reconnect.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "revoke")]
+pub struct RevokeToken {
+    #[argh(positional, description = "the name of the token")]
+    pub name: String,
+}
+
+/// List every API token.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "list")]
+pub struct ListTokens {}
+
+/// Manage user accounts. Every user has their own tasks.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "user")]
+pub struct UserCommand {
+    #[argh(subcommand)]
+    pub action: UserAction,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+pub enum UserAction {
+    Create(CreateUser),
+    Disable(DisableUser),
+    Enable(EnableUser),
+    List(ListUsers),
+}
+
+/// Create a new user. Use `token create` to let them log in.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "create")]
+pub struct CreateUser {
+    #[argh(positional, description = "the name of the new user")]
+    pub name: String,
+}
+
+/// Stop a user from authenticating. Their tasks and tokens are kept.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "disable")]
+pub struct DisableUser {
+    #[argh(positional, description = "the name of the user")]
+    pub name: String,
+}
+
+/// Let a disabled user authenticate again.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "enable")]
+pub struct EnableUser {
+    #[argh(positional, description = "the name of the user")]
+    pub name: String,
+}
+
+/// List every user.
+#[derive(Debug, FromArgs)]
+#[argh(subcommand, name = "list")]
+pub struct ListUsers {}
@@ -0,0 +1,139 @@
+use rusqlite::{params, OptionalExtension, Row};
+use yabusame::{
+    list::{validate_list_name, List, ListDelta, ListId},
+    YabuRpcError,
+};
+
+use super::Database;
+
+impl Database {
+    fn list_from_row(row: &Row) -> anyhow::Result<List> {
+        Ok(List {
+            id: Some(row.get::<_, u32>("list_id")?.try_into()?),
+            name: row.get("name")?,
+            archived: row.get("archived")?,
+        })
+    }
+
+    pub(super) fn validate_list(
+        &self,
+        owner: i64,
+        list: Option<ListId>,
+    ) -> anyhow::Result<Option<YabuRpcError>> {
+        match list {
+            Some(list_id) if self.get_list(owner, list_id)?.is_none() => {
+                Ok(Some(YabuRpcError::ListDoesntExist(list_id)))
+            }
+
+            _ => Ok(None),
+        }
+    }
+
+    /// Checks that `name` is valid and that no other list of the user's has it.
+    /// `list_id` is `None` for lists which haven't been added yet.
+    fn validate_list_name(
+        &self,
+        owner: i64,
+        list_id: Option<ListId>,
+        name: &str,
+    ) -> anyhow::Result<Option<YabuRpcError>> {
+        if validate_list_name(name).is_err() {
+            return Ok(Some(YabuRpcError::InvalidListName(name.to_string())));
+        }
+
+        let taken = self
+            .connection
+            .query_row(
+                "SELECT 1 FROM lists WHERE owner = ?1 AND name = ?2 AND list_id IS NOT ?3",
+                params![owner, name, list_id.map(|list_id| list_id.0.get())],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+
+        Ok(taken.then(|| YabuRpcError::ListNameTaken(name.to_string())))
+    }
+
+    pub fn get_list(&self, owner: i64, list_id: ListId) -> anyhow::Result<Option<List>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT * FROM lists WHERE list_id = ?1 AND owner = ?2")?;
+        let mut rows = statement.query(params![list_id.0.get(), owner])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(Self::list_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns every one of the user's lists, sorted by name.
+    pub fn lists(&self, owner: i64) -> anyhow::Result<Vec<List>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT * FROM lists WHERE owner = ?1 ORDER BY name")?;
+        let mut rows = statement.query(params![owner])?;
+        let mut res = Vec::new();
+
+        while let Some(row) = rows.next()? {
+            res.push(Self::list_from_row(row)?);
+        }
+
+        Ok(res)
+    }
+
+    /// Returns the list as it was stored.
+    pub fn add_list(&self, owner: i64, list: List) -> anyhow::Result<Result<List, YabuRpcError>> {
+        if let Some(err) = self.validate_list_name(owner, None, &list.name)? {
+            return Ok(Err(err));
+        }
+
+        self.connection.execute(
+            "INSERT INTO lists (owner, name, archived) VALUES (?1, ?2, ?3)",
+            params![owner, list.name, list.archived],
+        )?;
+
+        let list_id = u32::try_from(self.connection.last_insert_rowid())?.try_into()?;
+
+        Ok(Ok(List {
+            id: Some(list_id),
+            ..list
+        }))
+    }
+
+    /// Returns the list as it is after the update.
+    pub fn update_list(
+        &self,
+        owner: i64,
+        list_id: ListId,
+        delta: ListDelta,
+    ) -> anyhow::Result<Result<List, YabuRpcError>> {
+        let mut list = match self.get_list(owner, list_id)? {
+            Some(list) => list,
+            None => return Ok(Err(YabuRpcError::ListDoesntExist(list_id))),
+        };
+
+        list.apply_delta(delta);
+
+        if let Some(err) = self.validate_list_name(owner, Some(list_id), &list.name)? {
+            return Ok(Err(err));
+        }
+
+        self.connection.execute(
+            "UPDATE lists SET name = ?1, archived = ?2 WHERE list_id = ?3",
+            params![list.name, list.archived, list_id.0.get()],
+        )?;
+
+        Ok(Ok(list))
+    }
+
+    /// Returns whether there was a list to remove. Any tasks still in it
+    /// are removed by `ON DELETE CASCADE`.
+    pub fn remove_list(&self, owner: i64, list_id: ListId) -> anyhow::Result<bool> {
+        let removed = self.connection.execute(
+            "DELETE FROM lists WHERE list_id = ?1 AND owner = ?2",
+            params![list_id.0.get(), owner],
+        )?;
+
+        Ok(removed > 0)
+    }
+}
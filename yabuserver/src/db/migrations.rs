@@ -0,0 +1,76 @@
+use anyhow::bail;
+use rusqlite::Connection;
+
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    sql: &'static str,
+}
+
+macro_rules! migrations {
+    ($($version:literal: $description:literal => $file:literal,)*) => {
+        &[$(
+            Migration {
+                version: $version,
+                description: $description,
+                sql: include_str!(concat!("migrations/", $file)),
+            },
+        )*]
+    };
+}
+
+/// Every migration, in order. Migration `n` upgrades the database from
+/// schema version `n - 1` to version `n`. Never edit or remove a migration
+/// once it's been released; add a new one instead.
+pub const MIGRATIONS: &[Migration] = migrations! {
+    1: "create the tasks table" => "0001_create_tasks.sql",
+    2: "create the tags table" => "0002_create_tags.sql",
+    3: "create the api_tokens table" => "0003_create_api_tokens.sql",
+    4: "create the users table and give tasks and tokens owners" => "0004_create_users.sql",
+    5: "let tasks repeat" => "0005_add_recurrence.sql",
+    6: "let tasks have subtasks" => "0006_add_parent.sql",
+    7: "let tasks depend on each other" => "0007_create_dependencies.sql",
+    8: "create the lists table and let tasks belong to one" => "0008_create_lists.sql",
+    9: "let tasks have notes" => "0009_add_notes.sql",
+};
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Databases which predate migrations are at version 0.
+pub fn current_version(connection: &Connection) -> anyhow::Result<u32> {
+    Ok(connection.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+pub fn pending(connection: &Connection) -> anyhow::Result<&'static [Migration]> {
+    let version = current_version(connection)?;
+
+    // Running an old server against a newer database could lose data,
+    // so refuse to touch it at all
+    if version > latest_version() {
+        bail!(
+            "the database is at schema version {version}, but this version of yabuserver \
+            only understands versions up to {}",
+            latest_version()
+        );
+    }
+
+    Ok(&MIGRATIONS[version as usize..])
+}
+
+/// Returns the migrations which were applied.
+pub fn run(connection: &mut Connection) -> anyhow::Result<&'static [Migration]> {
+    let pending = pending(connection)?;
+
+    for migration in pending {
+        // Each migration is applied atomically along with its version bump,
+        // so a failed migration leaves the database at the previous version
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration.sql)?;
+        transaction.pragma_update(None, "user_version", migration.version)?;
+        transaction.commit()?;
+    }
+
+    Ok(pending)
+}
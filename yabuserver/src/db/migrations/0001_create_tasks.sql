@@ -0,0 +1,8 @@
+-- `IF NOT EXISTS` adopts databases created before migrations existed
+CREATE TABLE IF NOT EXISTS tasks (
+    task_id INTEGER PRIMARY KEY,
+    complete INTEGER CHECK(complete <= 1) NOT NULL,
+    description TEXT NOT NULL,
+    priority INTEGER NOT NULL,
+    due_date INTEGER
+);
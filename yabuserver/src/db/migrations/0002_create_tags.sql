@@ -0,0 +1,6 @@
+-- `IF NOT EXISTS` adopts databases created before migrations existed
+CREATE TABLE IF NOT EXISTS tags (
+    task_id INTEGER NOT NULL REFERENCES tasks(task_id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (task_id, tag)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE api_tokens (
+    token_id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    -- SHA-256 of the token. Tokens are random, so they don't need a salt
+    -- or a slow hash.
+    token_hash BLOB NOT NULL UNIQUE,
+    created INTEGER NOT NULL
+);
@@ -0,0 +1,18 @@
+CREATE TABLE users (
+    user_id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    disabled INTEGER CHECK(disabled <= 1) NOT NULL DEFAULT 0,
+    created INTEGER NOT NULL
+);
+
+-- Everything which predates user accounts is handed to `admin`
+INSERT INTO users (user_id, name, created) VALUES (1, 'admin', CAST(strftime('%s', 'now') AS INTEGER));
+
+-- SQLite won't add a foreign key column with a non-null default, so these
+-- are nullable. The server always fills them in.
+ALTER TABLE tasks ADD COLUMN owner INTEGER REFERENCES users(user_id) ON DELETE CASCADE;
+UPDATE tasks SET owner = 1;
+CREATE INDEX tasks_by_owner ON tasks (owner);
+
+ALTER TABLE api_tokens ADD COLUMN user_id INTEGER REFERENCES users(user_id) ON DELETE CASCADE;
+UPDATE api_tokens SET user_id = 1;
@@ -0,0 +1,2 @@
+-- A JSON-encoded `Recurrence`, or NULL for tasks which don't repeat
+ALTER TABLE tasks ADD COLUMN recurrence TEXT;
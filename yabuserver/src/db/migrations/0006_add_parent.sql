@@ -0,0 +1,3 @@
+-- Removing a task removes its subtasks too
+ALTER TABLE tasks ADD COLUMN parent INTEGER REFERENCES tasks(task_id) ON DELETE CASCADE;
+CREATE INDEX tasks_by_parent ON tasks (parent);
@@ -0,0 +1,7 @@
+CREATE TABLE dependencies (
+    task_id INTEGER NOT NULL REFERENCES tasks(task_id) ON DELETE CASCADE,
+    depends_on INTEGER NOT NULL REFERENCES tasks(task_id) ON DELETE CASCADE,
+    PRIMARY KEY (task_id, depends_on)
+);
+
+CREATE INDEX dependencies_by_depends_on ON dependencies (depends_on);
@@ -0,0 +1,11 @@
+CREATE TABLE lists (
+    list_id INTEGER PRIMARY KEY,
+    owner INTEGER NOT NULL REFERENCES users(user_id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    archived INTEGER CHECK(archived <= 1) NOT NULL DEFAULT 0,
+    UNIQUE (owner, name)
+);
+
+-- Removing a list removes its tasks too
+ALTER TABLE tasks ADD COLUMN list INTEGER REFERENCES lists(list_id) ON DELETE CASCADE;
+CREATE INDEX tasks_by_list ON tasks (list);
@@ -0,0 +1,2 @@
+-- Markdown, or NULL for tasks without notes
+ALTER TABLE tasks ADD COLUMN notes TEXT;
@@ -1,67 +1,563 @@
+mod lists;
+mod migrations;
+mod tokens;
+mod users;
+
+pub use migrations::Migration;
+pub use tokens::Token;
+pub use users::User;
+
 use anyhow::anyhow;
 use num_traits::{FromPrimitive, ToPrimitive};
-use rusqlite::{params, Connection, Row};
+use rusqlite::{params, params_from_iter, Connection, Row, ToSql};
+use std::{collections::BTreeSet, iter, thread};
 use time::OffsetDateTime;
-use yabusame::{Priority, Response, Task, TaskDelta, TaskId, YabuRpcError};
+use tokio::sync::{mpsc, oneshot};
+use yabusame::{
+    list::ListId,
+    recurrence::{Recurrence, MAX_INTERVAL},
+    validate_tag, Priority, Progress, SortKey, Task, TaskDelta, TaskId, TaskQuery, YabuRpcError,
+};
 
 pub const DEFAULT_DATABASE_URL: &str = "yabuserver.db";
 
+type Job = Box<dyn FnOnce(&mut Database) + Send>;
+
+/// A task which was changed by updating another, such as
+/// a subtask which was completed along with its parent.
+pub struct Cascaded {
+    pub previous: Task,
+    pub task: Task,
+}
+
+fn recurrence_to_sql(recurrence: &Option<Recurrence>) -> anyhow::Result<Option<String>> {
+    Ok(recurrence.as_ref().map(serde_json::to_string).transpose()?)
+}
+
+/// A cheaply cloneable handle to a `Database` running on its own thread.
+/// `rusqlite` is synchronous, so using it directly would block the runtime.
+#[derive(Clone)]
+pub struct DatabaseHandle {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+impl DatabaseHandle {
+    pub fn spawn(mut database: Database) -> anyhow::Result<Self> {
+        let (jobs, mut rx) = mpsc::unbounded_channel::<Job>();
+
+        // The thread exits once every handle has been dropped
+        thread::Builder::new()
+            .name("database".to_string())
+            .spawn(move || {
+                while let Some(job) = rx.blocking_recv() {
+                    job(&mut database);
+                }
+            })?;
+
+        Ok(Self { jobs })
+    }
+
+    /// Runs `f` on the database thread. Jobs are run one at a time,
+    /// in the order they were submitted.
+    pub async fn run<T, F>(&self, f: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Database) -> T + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+
+        self.jobs
+            .send(Box::new(move |database| {
+                // The receiver is only gone if the connection was dropped,
+                // in which case nobody cares about the result
+                let _ = tx.send(f(database));
+            }))
+            .map_err(|_| anyhow!("the database thread has stopped"))?;
+
+        Ok(rx.await?)
+    }
+}
+
 pub struct Database {
     connection: Connection,
 }
 
 impl Database {
-    pub fn connect(database_url: &str) -> anyhow::Result<Self> {
-        let mut connection = Connection::open(database_url)?;
-
-        // TODO: hardcoded
-        connection.execute(
-            "CREATE TABLE IF NOT EXISTS tasks (
-                task_id INTEGER PRIMARY KEY,
-                complete INTEGER CHECK(complete <= 1) NOT NULL,
-                description TEXT NOT NULL,
-                priority INTEGER NOT NULL,
-                due_date INTEGER
-            )",
-            [],
+    /// Opens the database without migrating it.
+    pub fn open(database_url: &str) -> anyhow::Result<Self> {
+        let connection = Connection::open(database_url)?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        Ok(Self { connection })
+    }
+
+    pub fn schema_version(&self) -> anyhow::Result<u32> {
+        migrations::current_version(&self.connection)
+    }
+
+    pub fn pending_migrations(&self) -> anyhow::Result<&'static [Migration]> {
+        migrations::pending(&self.connection)
+    }
+
+    /// Returns the migrations which were applied.
+    pub fn migrate(&mut self) -> anyhow::Result<&'static [Migration]> {
+        migrations::run(&mut self.connection)
+    }
+
+    /// Runs `f` inside a savepoint, rolling back everything it did if it fails.
+    /// Unlike transactions, savepoints can be nested.
+    fn atomically<T>(&self, f: impl FnOnce() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        self.connection.execute_batch("SAVEPOINT atomically")?;
+
+        match f() {
+            Ok(res) => {
+                self.connection.execute_batch("RELEASE atomically")?;
+                Ok(res)
+            }
+
+            Err(err) => {
+                self.connection
+                    .execute_batch("ROLLBACK TO atomically; RELEASE atomically")?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Like `atomically`, but also rolls back if `f` returns a `YabuRpcError`.
+    /// Everything `f` does is committed at once.
+    pub fn all_or_nothing<T>(
+        &self,
+        f: impl FnOnce() -> anyhow::Result<Result<T, YabuRpcError>>,
+    ) -> anyhow::Result<Result<T, YabuRpcError>> {
+        self.connection.execute_batch("SAVEPOINT all_or_nothing")?;
+        let res = f();
+
+        if let Ok(Ok(_)) = res {
+            self.connection.execute_batch("RELEASE all_or_nothing")?;
+        } else {
+            self.connection
+                .execute_batch("ROLLBACK TO all_or_nothing; RELEASE all_or_nothing")?;
+        }
+
+        res
+    }
+
+    fn validate_tags(tags: &BTreeSet<String>) -> Option<YabuRpcError> {
+        tags.iter()
+            .find(|tag| validate_tag(tag).is_err())
+            .map(|tag| YabuRpcError::InvalidTag(tag.clone()))
+    }
+
+    fn validate_recurrence(recurrence: &Option<Recurrence>) -> Option<YabuRpcError> {
+        recurrence
+            .as_ref()
+            .filter(|recurrence| recurrence.interval.get() > MAX_INTERVAL)
+            .map(|recurrence| {
+                YabuRpcError::InvalidRequest(format!(
+                    "can't repeat every {recurrence} (the interval can be at most {MAX_INTERVAL})"
+                ))
+            })
+    }
+
+    fn set_tags(&self, task_id: TaskId, tags: &BTreeSet<String>) -> anyhow::Result<()> {
+        self.connection.execute(
+            "DELETE FROM tags WHERE task_id = ?1",
+            params![task_id.0.get()],
         )?;
 
-        Ok(Self { connection })
+        let mut statement = self
+            .connection
+            .prepare("INSERT INTO tags (task_id, tag) VALUES (?1, ?2)")?;
+
+        for tag in tags {
+            statement.execute(params![task_id.0.get(), tag])?;
+        }
+
+        Ok(())
+    }
+
+    fn tags_of(&self, task_id: TaskId) -> anyhow::Result<BTreeSet<String>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT tag FROM tags WHERE task_id = ?1")?;
+        let tags = statement
+            .query_map(params![task_id.0.get()], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        Ok(tags)
     }
 
-    pub fn add_task(&self, task: Task) -> anyhow::Result<()> {
+    /// Subtasks of subtasks and so on, but not `task_id` itself.
+    fn descendants_of(&self, task_id: TaskId) -> anyhow::Result<Vec<TaskId>> {
+        let mut statement = self.connection.prepare(
+            "WITH RECURSIVE descendants (task_id) AS (
+                SELECT task_id FROM tasks WHERE parent = ?1
+                UNION
+                SELECT tasks.task_id FROM tasks JOIN descendants ON tasks.parent = descendants.task_id
+            )
+            SELECT task_id FROM descendants",
+        )?;
+
+        let descendants = statement
+            .query_map(params![task_id.0.get()], |row| row.get::<_, u32>(0))?
+            .map(|task_id| Ok(task_id?.try_into()?))
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(descendants)
+    }
+
+    /// Checks that `parent` can be the parent of `task_id`, which is `None`
+    /// for tasks which haven't been added yet.
+    fn validate_parent(
+        &self,
+        owner: i64,
+        task_id: Option<TaskId>,
+        parent: Option<TaskId>,
+    ) -> anyhow::Result<Option<YabuRpcError>> {
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return Ok(None),
+        };
+
+        if self.get_task(owner, parent)?.is_none() {
+            return Ok(Some(YabuRpcError::TaskDoesntExist(parent)));
+        }
+
+        let task_id = match task_id {
+            Some(task_id) => task_id,
+            None => return Ok(None),
+        };
+
+        if parent == task_id || self.descendants_of(task_id)?.contains(&parent) {
+            return Ok(Some(YabuRpcError::SubtaskCycle {
+                task: task_id,
+                parent,
+            }));
+        }
+
+        Ok(None)
+    }
+
+    fn progress_of(&self, task_id: TaskId) -> anyhow::Result<Option<Progress>> {
+        let (complete, total) = self.connection.query_row(
+            "SELECT coalesce(sum(complete), 0), count(*) FROM tasks WHERE parent = ?1",
+            params![task_id.0.get()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        Ok((total > 0).then_some(Progress { complete, total }))
+    }
+
+    fn set_dependencies(
+        &self,
+        task_id: TaskId,
+        depends_on: &BTreeSet<TaskId>,
+    ) -> anyhow::Result<()> {
         self.connection.execute(
-            "INSERT INTO tasks (complete, description, priority, due_date) VALUES (?1, ?2, ?3, ?4)",
-            params![
-                task.complete,
-                task.description,
-                task.priority.to_u32(),
-                task.due_date.map(|due_date| due_date.unix_timestamp()),
-            ],
+            "DELETE FROM dependencies WHERE task_id = ?1",
+            params![task_id.0.get()],
         )?;
 
+        let mut statement = self
+            .connection
+            .prepare("INSERT INTO dependencies (task_id, depends_on) VALUES (?1, ?2)")?;
+
+        for dependency in depends_on {
+            statement.execute(params![task_id.0.get(), dependency.0.get()])?;
+        }
+
         Ok(())
     }
 
+    /// Returns every task `task_id` depends on, and which of those are incomplete.
+    fn dependencies_of(
+        &self,
+        task_id: TaskId,
+    ) -> anyhow::Result<(BTreeSet<TaskId>, BTreeSet<TaskId>)> {
+        let mut statement = self.connection.prepare(
+            "SELECT depends_on, complete FROM dependencies
+            JOIN tasks ON tasks.task_id = dependencies.depends_on
+            WHERE dependencies.task_id = ?1",
+        )?;
+
+        let mut rows = statement.query(params![task_id.0.get()])?;
+        let (mut depends_on, mut blocked_by) = (BTreeSet::new(), BTreeSet::new());
+
+        while let Some(row) = rows.next()? {
+            let dependency = TaskId::try_from(row.get::<_, u32>(0)?)?;
+
+            if !row.get::<_, bool>(1)? {
+                blocked_by.insert(dependency);
+            }
+
+            depends_on.insert(dependency);
+        }
+
+        Ok((depends_on, blocked_by))
+    }
+
+    /// Checks that `task_id` can depend on everything in `depends_on`.
+    /// `task_id` is `None` for tasks which haven't been added yet.
+    fn validate_dependencies(
+        &self,
+        owner: i64,
+        task_id: Option<TaskId>,
+        depends_on: &BTreeSet<TaskId>,
+    ) -> anyhow::Result<Option<YabuRpcError>> {
+        for &dependency in depends_on {
+            if self.get_task(owner, dependency)?.is_none() {
+                return Ok(Some(YabuRpcError::TaskDoesntExist(dependency)));
+            }
+        }
+
+        let task_id = match task_id {
+            Some(task_id) => task_id,
+            None => return Ok(None),
+        };
+
+        // Everything which already depends on `task_id`, directly or not
+        let mut statement = self.connection.prepare(
+            "WITH RECURSIVE dependents (task_id) AS (
+                SELECT ?1
+                UNION
+                SELECT dependencies.task_id FROM dependencies
+                JOIN dependents ON dependencies.depends_on = dependents.task_id
+            )
+            SELECT task_id FROM dependents",
+        )?;
+
+        let dependents = statement
+            .query_map(params![task_id.0.get()], |row| row.get::<_, u32>(0))?
+            .map(|task_id| Ok(TaskId::try_from(task_id?)?))
+            .collect::<anyhow::Result<BTreeSet<_>>>()?;
+
+        match depends_on.intersection(&dependents).next() {
+            Some(&dependency) => Ok(Some(YabuRpcError::DependencyCycle {
+                task: task_id,
+                dependency,
+            })),
+
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the tasks which depend on `task_id` or any of its subtasks,
+    /// since those are completed and removed along with it.
+    pub fn dependents_of(&self, owner: i64, task_id: TaskId) -> anyhow::Result<Vec<Task>> {
+        let tree = iter::once(task_id).chain(self.descendants_of(task_id)?);
+        let mut statement = self
+            .connection
+            .prepare("SELECT task_id FROM dependencies WHERE depends_on = ?1")?;
+        let mut dependents = BTreeSet::new();
+
+        for task_id in tree {
+            for dependent in
+                statement.query_map(params![task_id.0.get()], |row| row.get::<_, u32>(0))?
+            {
+                dependents.insert(TaskId::try_from(dependent?)?);
+            }
+        }
+
+        let mut res = Vec::new();
+
+        for dependent in dependents {
+            res.extend(self.get_task(owner, dependent)?);
+        }
+
+        Ok(res)
+    }
+
+    /// Returns the task as it was stored.
+    pub fn add_task(&self, owner: i64, task: Task) -> anyhow::Result<Result<Task, YabuRpcError>> {
+        if let Some(err) = Self::validate_tags(&task.tags) {
+            return Ok(Err(err));
+        }
+
+        if let Some(err) = Self::validate_recurrence(&task.recurrence) {
+            return Ok(Err(err));
+        }
+
+        if let Some(err) = self.validate_parent(owner, None, task.parent)? {
+            return Ok(Err(err));
+        }
+
+        if let Some(err) = self.validate_dependencies(owner, None, &task.depends_on)? {
+            return Ok(Err(err));
+        }
+
+        if let Some(err) = self.validate_list(owner, task.list)? {
+            return Ok(Err(err));
+        }
+
+        let task_id = self.atomically(|| {
+            self.connection.execute(
+                "INSERT INTO tasks
+                    (complete, description, priority, due_date, owner, recurrence, parent, list,
+                    notes)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    task.complete,
+                    task.description,
+                    task.priority.to_u32(),
+                    task.due_date.map(|due_date| due_date.unix_timestamp()),
+                    owner,
+                    recurrence_to_sql(&task.recurrence)?,
+                    task.parent.map(|parent| parent.0.get()),
+                    task.list.map(|list| list.0.get()),
+                    task.notes,
+                ],
+            )?;
+
+            let task_id = u32::try_from(self.connection.last_insert_rowid())?.try_into()?;
+            self.set_tags(task_id, &task.tags)?;
+            self.set_dependencies(task_id, &task.depends_on)?;
+            Ok(task_id)
+        })?;
+
+        let (_, blocked_by) = self.dependencies_of(task_id)?;
+
+        Ok(Ok(Task {
+            id: Some(task_id),
+            progress: None,
+            blocked_by,
+            ..task
+        }))
+    }
+
     fn task_from_row(&self, row: &Row) -> anyhow::Result<Task> {
+        let task_id: TaskId = row.get::<_, u32>(0)?.try_into()?;
         let priority = row.get(3)?;
 
-        Ok(Task::new(
-            Some(row.get::<_, u32>(0)?.try_into()?),
-            row.get::<_, bool>(1)?,
-            row.get::<_, String>(2)?,
-            Priority::from_u32(priority)
-                .ok_or_else(|| anyhow!("can't convert {} to a `Priority`", priority))?,
-            row.get::<_, Option<i64>>(4)?
-                .map(OffsetDateTime::from_unix_timestamp)
+        let recurrence = row
+            .get::<_, Option<String>>("recurrence")?
+            .map(|recurrence| serde_json::from_str(&recurrence))
+            .transpose()?;
+
+        let parent = row
+            .get::<_, Option<u32>>("parent")?
+            .map(TaskId::try_from)
+            .transpose()?;
+
+        let (depends_on, blocked_by) = self.dependencies_of(task_id)?;
+
+        Ok(Task {
+            tags: self.tags_of(task_id)?,
+            recurrence,
+            parent,
+            progress: self.progress_of(task_id)?,
+            depends_on,
+            blocked_by,
+            list: row
+                .get::<_, Option<u32>>("list")?
+                .map(ListId::try_from)
                 .transpose()?,
-        ))
+            notes: row.get("notes")?,
+            ..Task::new(
+                Some(task_id),
+                row.get::<_, bool>(1)?,
+                row.get::<_, String>(2)?,
+                Priority::from_u32(priority)
+                    .ok_or_else(|| anyhow!("can't convert {} to a `Priority`", priority))?,
+                row.get::<_, Option<i64>>(4)?
+                    .map(OffsetDateTime::from_unix_timestamp)
+                    .transpose()?,
+            )
+        })
     }
 
-    pub fn all_tasks(&self) -> anyhow::Result<Vec<Task>> {
+    pub fn query_tasks(&self, owner: i64, query: &TaskQuery) -> anyhow::Result<Vec<Task>> {
+        // Every condition uses exactly one anonymous parameter,
+        // so `conditions` and `values` line up.
+        let mut conditions = vec!["owner = ?"];
+        let mut values: Vec<Box<dyn ToSql>> = vec![Box::new(owner)];
+
+        if let Some(complete) = query.complete {
+            conditions.push("complete = ?");
+            values.push(Box::new(complete));
+        }
+
+        if let Some(min_priority) = query.min_priority {
+            conditions.push("priority >= ?");
+            values.push(Box::new(min_priority.to_u32()));
+        }
+
+        if let Some(max_priority) = query.max_priority {
+            conditions.push("priority <= ?");
+            values.push(Box::new(max_priority.to_u32()));
+        }
+
+        if let Some(due_before) = query.due_before {
+            conditions.push("due_date < ?");
+            values.push(Box::new(due_before.unix_timestamp()));
+        }
+
+        if let Some(due_after) = query.due_after {
+            conditions.push("due_date > ?");
+            values.push(Box::new(due_after.unix_timestamp()));
+        }
+
+        if query.overdue {
+            conditions.push("complete = 0 AND due_date < ?");
+            values.push(Box::new(OffsetDateTime::now_utc().unix_timestamp()));
+        }
+
+        if let Some(text) = &query.text {
+            conditions.push("instr(lower(description), lower(?)) > 0");
+            values.push(Box::new(text.clone()));
+        }
+
+        for tag in &query.tags {
+            conditions.push("task_id IN (SELECT task_id FROM tags WHERE tag = ?)");
+            values.push(Box::new(tag.clone()));
+        }
+
+        if let Some(list) = query.list {
+            conditions.push("list = ?");
+            values.push(Box::new(list.0.get()));
+        }
+
+        if query.ready {
+            conditions.push(
+                "complete = ? AND task_id NOT IN (
+                    SELECT dependencies.task_id FROM dependencies
+                    JOIN tasks AS dependency ON dependency.task_id = dependencies.depends_on
+                    WHERE dependency.complete = 0
+                )",
+            );
+            values.push(Box::new(false));
+        }
+
+        let mut sql = String::from("SELECT * FROM tasks WHERE ");
+        sql.push_str(&conditions.join(" AND "));
+
+        let order = query
+            .sort
+            .iter()
+            .map(|sort| {
+                let direction = if sort.descending { "DESC" } else { "ASC" };
+
+                match sort.key {
+                    SortKey::Id => format!("task_id {direction}"),
+                    SortKey::DueDate => format!("due_date IS NULL, due_date {direction}"),
+                    SortKey::Priority => format!("priority {direction}"),
+                }
+            })
+            // break ties consistently so that pagination is stable
+            .chain(iter::once("task_id ASC".to_string()))
+            .collect::<Vec<_>>();
+
+        sql.push_str(" ORDER BY ");
+        sql.push_str(&order.join(", "));
+
+        if query.limit.is_some() || query.offset > 0 {
+            // SQLite treats a negative limit as "no limit"
+            sql.push_str(" LIMIT ? OFFSET ?");
+            values.push(Box::new(query.limit.map_or(-1, i64::from)));
+            values.push(Box::new(query.offset));
+        }
+
         let mut res = Vec::new();
-        let mut statement = self.connection.prepare("SELECT * FROM tasks")?;
-        let mut rows = statement.query([])?;
+        let mut statement = self.connection.prepare(&sql)?;
+        let mut rows = statement.query(params_from_iter(&values))?;
 
         while let Some(row) = rows.next()? {
             res.push(self.task_from_row(row)?);
@@ -70,47 +566,325 @@ impl Database {
         Ok(res)
     }
 
-    fn get_task(&self, task_id: TaskId) -> anyhow::Result<Option<Task>> {
+    pub fn get_task(&self, owner: i64, task_id: TaskId) -> anyhow::Result<Option<Task>> {
         let mut statement = self
             .connection
-            .prepare("SELECT * FROM tasks WHERE task_id = ?1")?;
-        let mut rows = statement.query(params![task_id.0.get()])?;
+            .prepare("SELECT * FROM tasks WHERE task_id = ?1 AND owner = ?2")?;
+        let mut rows = statement.query(params![task_id.0.get(), owner])?;
 
         match rows.next()? {
             Some(row) => Ok(Some(self.task_from_row(row)?)),
-            None => todo!(),
+            None => Ok(None),
         }
     }
 
-    pub fn update_task(&self, task_id: TaskId, task_delta: TaskDelta) -> anyhow::Result<Response> {
-        let mut task = match self.get_task(task_id)? {
+    /// Returns the task as it is after the update. Completing a task
+    /// completes all of its subtasks too, which are returned separately.
+    pub fn update_task(
+        &self,
+        owner: i64,
+        task_id: TaskId,
+        task_delta: TaskDelta,
+    ) -> anyhow::Result<Result<(Task, Vec<Cascaded>), YabuRpcError>> {
+        // Other users' tasks are treated as if they don't exist
+        let previous = match self.get_task(owner, task_id)? {
             Some(task) => task,
-            None => return Ok(Response::Error(YabuRpcError::TaskDoesntExist(task_id))),
+            None => return Ok(Err(YabuRpcError::TaskDoesntExist(task_id))),
         };
 
+        let mut task = previous.clone();
         task.apply_delta(task_delta);
 
-        self.connection.execute(
-            "UPDATE tasks
-            SET complete = ?1, description = ?2, priority = ?3, due_date = ?4
-            WHERE task_id = ?5",
-            params![
-                task.complete,
-                task.description,
-                task.priority.to_u32(),
-                task.due_date.map(|due_date| due_date.unix_timestamp()),
-                task_id.0.get(),
-            ],
-        )?;
+        if let Some(err) = Self::validate_tags(&task.tags) {
+            return Ok(Err(err));
+        }
+
+        if let Some(err) = Self::validate_recurrence(&task.recurrence) {
+            return Ok(Err(err));
+        }
+
+        if let Some(err) = self.validate_parent(owner, Some(task_id), task.parent)? {
+            return Ok(Err(err));
+        }
+
+        if let Some(err) = self.validate_dependencies(owner, Some(task_id), &task.depends_on)? {
+            return Ok(Err(err));
+        }
 
-        Ok(Response::Nothing)
+        if let Some(err) = self.validate_list(owner, task.list)? {
+            return Ok(Err(err));
+        }
+
+        let completed = self.atomically(|| {
+            self.connection.execute(
+                "UPDATE tasks
+                SET complete = ?1, description = ?2, priority = ?3, due_date = ?4,
+                    recurrence = ?5, parent = ?6, list = ?7, notes = ?8
+                WHERE task_id = ?9",
+                params![
+                    task.complete,
+                    task.description,
+                    task.priority.to_u32(),
+                    task.due_date.map(|due_date| due_date.unix_timestamp()),
+                    recurrence_to_sql(&task.recurrence)?,
+                    task.parent.map(|parent| parent.0.get()),
+                    task.list.map(|list| list.0.get()),
+                    task.notes,
+                    task_id.0.get(),
+                ],
+            )?;
+
+            self.set_tags(task_id, &task.tags)?;
+            self.set_dependencies(task_id, &task.depends_on)?;
+
+            let mut completed = Vec::new();
+
+            if task.complete && !previous.complete {
+                for descendant in self.descendants_of(task_id)? {
+                    match self.get_task(owner, descendant)? {
+                        Some(subtask) if !subtask.complete => completed.push(subtask),
+                        _ => continue,
+                    }
+
+                    self.connection.execute(
+                        "UPDATE tasks SET complete = 1 WHERE task_id = ?1",
+                        params![descendant.0.get()],
+                    )?;
+                }
+            }
+
+            Ok(completed)
+        })?;
+
+        // Fetched again since their progress changed
+        let fetch_again = |task: &Task| -> anyhow::Result<Task> {
+            let task_id = task.id_or_error()?;
+            let task = self.get_task(owner, task_id)?;
+            task.ok_or_else(|| anyhow!("task {task_id} disappeared while being updated"))
+        };
+
+        let cascaded = completed
+            .into_iter()
+            .map(|previous| {
+                let task = fetch_again(&previous)?;
+                Ok(Cascaded { previous, task })
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Ok((fetch_again(&task)?, cascaded)))
     }
 
-    pub fn remove_task(&self, task_id: TaskId) -> anyhow::Result<()> {
+    /// Returns every task which was removed: the task itself, then all of its
+    /// subtasks. Returns nothing if there was no task to remove.
+    pub fn remove_task(&self, owner: i64, task_id: TaskId) -> anyhow::Result<Vec<Task>> {
+        let task = match self.get_task(owner, task_id)? {
+            Some(task) => task,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut removed = vec![task];
+
+        for descendant in self.descendants_of(task_id)? {
+            removed.extend(self.get_task(owner, descendant)?);
+        }
+
+        // Subtasks are removed by `ON DELETE CASCADE`
         self.connection.execute(
-            "DELETE FROM tasks WHERE task_id = ?1",
-            params![task_id.0.get()],
+            "DELETE FROM tasks WHERE task_id = ?1 AND owner = ?2",
+            params![task_id.0.get(), owner],
         )?;
-        Ok(())
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add, database, new_task};
+    use std::num::NonZeroU32;
+    use yabusame::{recurrence::Frequency, Delta, SetDelta};
+
+    fn subtask_of(parent: TaskId, description: &'static str) -> Task {
+        Task {
+            parent: Some(parent),
+            ..new_task(description)
+        }
+    }
+
+    fn depending_on(dependency: TaskId, description: &'static str) -> Task {
+        Task {
+            depends_on: BTreeSet::from([dependency]),
+            ..new_task(description)
+        }
+    }
+
+    fn update(
+        database: &Database,
+        owner: i64,
+        task_id: TaskId,
+        task_delta: TaskDelta,
+    ) -> Result<(Task, Vec<Cascaded>), YabuRpcError> {
+        database.update_task(owner, task_id, task_delta).unwrap()
+    }
+
+    fn update_error(
+        database: &Database,
+        owner: i64,
+        task_id: TaskId,
+        task_delta: TaskDelta,
+    ) -> YabuRpcError {
+        match update(database, owner, task_id, task_delta) {
+            Ok(_) => panic!("updating task {task_id} was allowed"),
+            Err(err) => err,
+        }
+    }
+
+    fn set_parent(parent: TaskId) -> TaskDelta {
+        TaskDelta {
+            parent: Delta::Changed(Some(parent)),
+            ..TaskDelta::default()
+        }
+    }
+
+    fn add_dependency(dependency: TaskId) -> TaskDelta {
+        TaskDelta {
+            depends_on: vec![SetDelta::Add(dependency)],
+            ..TaskDelta::default()
+        }
+    }
+
+    #[test]
+    fn task_cant_be_its_own_subtask() {
+        let (database, owner) = database();
+        let task = add(&database, owner, new_task("task"));
+
+        match update_error(&database, owner, task, set_parent(task)) {
+            YabuRpcError::SubtaskCycle { task: t, parent } => assert_eq!((t, parent), (task, task)),
+            err => panic!("unexpected error {err}"),
+        }
+    }
+
+    #[test]
+    fn subtasks_cant_form_a_cycle() {
+        let (database, owner) = database();
+        let a = add(&database, owner, new_task("a"));
+        let b = add(&database, owner, subtask_of(a, "b"));
+
+        match update_error(&database, owner, a, set_parent(b)) {
+            YabuRpcError::SubtaskCycle { task, parent } => assert_eq!((task, parent), (a, b)),
+            err => panic!("unexpected error {err}"),
+        }
+        assert_eq!(database.get_task(owner, a).unwrap().unwrap().parent, None);
+    }
+
+    #[test]
+    fn completing_a_task_completes_all_of_its_descendants() {
+        let (database, owner) = database();
+        let parent = add(&database, owner, new_task("parent"));
+        let child = add(&database, owner, subtask_of(parent, "child"));
+        let grandchild = add(&database, owner, subtask_of(child, "grandchild"));
+
+        let complete = TaskDelta {
+            complete: Delta::Changed(true),
+            ..TaskDelta::default()
+        };
+        let (task, cascaded) = update(&database, owner, parent, complete).unwrap();
+        assert!(task.complete);
+
+        let mut cascaded = cascaded
+            .iter()
+            .map(|cascaded| {
+                assert!(!cascaded.previous.complete);
+                assert!(cascaded.task.complete);
+                cascaded.task.id.unwrap()
+            })
+            .collect::<Vec<_>>();
+        cascaded.sort();
+        assert_eq!(cascaded, [child, grandchild]);
+
+        for task_id in [child, grandchild] {
+            assert!(database.get_task(owner, task_id).unwrap().unwrap().complete);
+        }
+    }
+
+    #[test]
+    fn task_cant_depend_on_itself() {
+        let (database, owner) = database();
+        let task = add(&database, owner, new_task("task"));
+
+        match update_error(&database, owner, task, add_dependency(task)) {
+            YabuRpcError::DependencyCycle {
+                task: t,
+                dependency,
+            } => assert_eq!((t, dependency), (task, task)),
+            err => panic!("unexpected error {err}"),
+        }
+    }
+
+    #[test]
+    fn dependencies_cant_form_a_cycle() {
+        let (database, owner) = database();
+        let a = add(&database, owner, new_task("a"));
+        let b = add(&database, owner, depending_on(a, "b"));
+
+        match update_error(&database, owner, a, add_dependency(b)) {
+            YabuRpcError::DependencyCycle { task, dependency } => {
+                assert_eq!((task, dependency), (a, b))
+            }
+            err => panic!("unexpected error {err}"),
+        }
+        assert!(database.dependents_of(owner, b).unwrap().is_empty());
+    }
+
+    #[test]
+    fn ready_tasks_exclude_blocked_ones() {
+        let (database, owner) = database();
+        let dependency = add(&database, owner, new_task("dependency"));
+        let blocked = add(&database, owner, depending_on(dependency, "blocked"));
+
+        let ready = TaskQuery {
+            ready: true,
+            ..TaskQuery::default()
+        };
+        let ready_ids = || {
+            let tasks = database.query_tasks(owner, &ready).unwrap();
+            tasks
+                .iter()
+                .map(|task| task.id.unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ready_ids(), [dependency]);
+
+        let complete = TaskDelta {
+            complete: Delta::Changed(true),
+            ..TaskDelta::default()
+        };
+        update(&database, owner, dependency, complete).unwrap();
+        assert_eq!(ready_ids(), [blocked]);
+    }
+
+    #[test]
+    fn huge_repeat_intervals_are_rejected() {
+        let (database, owner) = database();
+        let recurrence = Recurrence {
+            interval: NonZeroU32::new(u32::MAX).unwrap(),
+            ..Recurrence::new(Frequency::Daily)
+        };
+
+        let task = Task {
+            recurrence: Some(recurrence.clone()),
+            ..new_task("task")
+        };
+        let res = database.add_task(owner, task).unwrap();
+        assert!(matches!(res, Err(YabuRpcError::InvalidRequest(_))));
+
+        let task = add(&database, owner, new_task("task"));
+        let repeat = TaskDelta {
+            recurrence: Delta::Changed(Some(recurrence)),
+            ..TaskDelta::default()
+        };
+        let err = update_error(&database, owner, task, repeat);
+        assert!(matches!(err, YabuRpcError::InvalidRequest(_)), "{err}");
     }
 }
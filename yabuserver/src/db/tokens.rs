@@ -0,0 +1,108 @@
+use rand::{rngs::OsRng, RngCore};
+use rusqlite::{params, OptionalExtension, Row};
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+
+use super::Database;
+
+const TOKEN_PREFIX: &str = "yabu_";
+const TOKEN_BYTES: usize = 32;
+
+fn hash_token(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub id: i64,
+    pub name: String,
+    pub created: OffsetDateTime,
+    pub user_id: i64,
+    pub user_name: String,
+}
+
+const TOKEN_COLUMNS: &str = "api_tokens.token_id, api_tokens.name, api_tokens.created, \
+    users.user_id, users.name FROM api_tokens JOIN users USING (user_id)";
+
+fn token_from_row(row: &Row) -> anyhow::Result<Token> {
+    Ok(Token {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        created: OffsetDateTime::from_unix_timestamp(row.get(2)?)?,
+        user_id: row.get(3)?,
+        user_name: row.get(4)?,
+    })
+}
+
+impl Database {
+    /// Returns a new token which authenticates as `user_id`. Only its hash
+    /// is stored, so it can't be recovered later.
+    pub fn create_token(&self, name: &str, user_id: i64) -> anyhow::Result<String> {
+        let mut bytes = [0u8; TOKEN_BYTES];
+        OsRng.fill_bytes(&mut bytes);
+        let token = format!("{TOKEN_PREFIX}{}", hex::encode(bytes));
+
+        self.connection.execute(
+            "INSERT INTO api_tokens (name, token_hash, created, user_id) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                name,
+                hash_token(&token),
+                OffsetDateTime::now_utc().unix_timestamp(),
+                user_id,
+            ],
+        )?;
+
+        Ok(token)
+    }
+
+    /// Returns `false` if there was no token with this name.
+    pub fn revoke_token(&self, name: &str) -> anyhow::Result<bool> {
+        let deleted = self
+            .connection
+            .execute("DELETE FROM api_tokens WHERE name = ?1", params![name])?;
+
+        Ok(deleted > 0)
+    }
+
+    pub fn tokens(&self) -> anyhow::Result<Vec<Token>> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("SELECT {TOKEN_COLUMNS} ORDER BY api_tokens.name"))?;
+        let mut rows = statement.query([])?;
+        let mut res = Vec::new();
+
+        while let Some(row) = rows.next()? {
+            res.push(token_from_row(row)?);
+        }
+
+        Ok(res)
+    }
+
+    /// Returns the token's details if it's valid and its user isn't disabled.
+    pub fn authenticate(&self, token: &str) -> anyhow::Result<Option<Token>> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {TOKEN_COLUMNS} WHERE api_tokens.token_hash = ?1 AND NOT users.disabled"
+        ))?;
+        let mut rows = statement.query(params![hash_token(token)])?;
+
+        match rows.next()? {
+            Some(row) => Ok(Some(token_from_row(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Whether a token which already authenticated still would, since it
+    /// could have been removed or its user disabled in the meantime.
+    pub fn token_is_valid(&self, token_id: i64) -> anyhow::Result<bool> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT 1 FROM api_tokens JOIN users USING (user_id)
+                WHERE api_tokens.token_id = ?1 AND NOT users.disabled",
+                params![token_id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+}
@@ -0,0 +1,69 @@
+use anyhow::bail;
+use rusqlite::{params, OptionalExtension};
+use time::OffsetDateTime;
+
+use super::Database;
+
+#[derive(Clone, Debug)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    pub disabled: bool,
+    pub created: OffsetDateTime,
+}
+
+impl Database {
+    pub fn create_user(&self, name: &str) -> anyhow::Result<i64> {
+        if self.user_id(name)?.is_some() {
+            bail!("there is already a user named `{name}`");
+        }
+
+        self.connection.execute(
+            "INSERT INTO users (name, created) VALUES (?1, ?2)",
+            params![name, OffsetDateTime::now_utc().unix_timestamp()],
+        )?;
+
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    pub fn user_id(&self, name: &str) -> anyhow::Result<Option<i64>> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT user_id FROM users WHERE name = ?1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Disabled users can't authenticate, but keep their tasks and tokens.
+    /// Returns `false` if there was no user with this name.
+    pub fn set_user_disabled(&self, name: &str, disabled: bool) -> anyhow::Result<bool> {
+        let updated = self.connection.execute(
+            "UPDATE users SET disabled = ?1 WHERE name = ?2",
+            params![disabled, name],
+        )?;
+
+        Ok(updated > 0)
+    }
+
+    pub fn users(&self) -> anyhow::Result<Vec<User>> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT user_id, name, disabled, created FROM users ORDER BY name")?;
+        let mut rows = statement.query([])?;
+        let mut res = Vec::new();
+
+        while let Some(row) = rows.next()? {
+            res.push(User {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                disabled: row.get(2)?,
+                created: OffsetDateTime::from_unix_timestamp(row.get(3)?)?,
+            });
+        }
+
+        Ok(res)
+    }
+}
@@ -1,91 +1,1165 @@
 #![allow(unused)]
 #![warn(unused_imports, unused_must_use)]
 
+mod args;
 mod db;
+#[cfg(test)]
+mod testing;
+mod tls;
 
-use argh::FromArgs;
-use db::{Database, DEFAULT_DATABASE_URL};
-use std::{net::IpAddr, io};
-use tokio::net::{TcpListener, TcpStream};
-use yabusame::{Message, Response, DEFAULT_SERVER_PORT, YabuError};
-
-/// The server behind the Yabusame todo list.
-#[derive(FromArgs)]
-struct Args {
-    #[argh(
-        option,
-        short = 'a',
-        description = "address to listen on",
-        default = "[0, 0, 0, 0].into()"
-    )]
-    listen_address: IpAddr,
-
-    #[argh(
-        option,
-        short = 'p',
-        description = "port to serve on",
-        default = "DEFAULT_SERVER_PORT"
-    )]
-    port: u16,
-}
-
-async fn handle_connection(mut socket: TcpStream) -> anyhow::Result<()> {
-    loop {
-        socket.readable().await?;
+use anyhow::{anyhow, bail};
+use args::{
+    Args, DisableUser, EnableUser, Migrate, Subcommand, TokenAction, TokenCommand, UserAction,
+    UserCommand,
+};
+use db::{Database, DatabaseHandle, Token, User};
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, future,
+    path::Path,
+    sync::Arc,
+};
+use time::OffsetDateTime;
+use tokio::{
+    io::{self, AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, Semaphore,
+    },
+};
+use tokio_rustls::TlsAcceptor;
+use yabusame::{
+    codec::{Codec, Framing},
+    Capability, Delta, Hello, HelloResponse, Message, Request, RequestId, Response, ServerFrame,
+    Task, TaskDelta, TaskEvent, TaskId, TaskQuery, YabuError, YabuRpcError, DATE_TIME_FORMAT,
+    PROTOCOL_VERSION,
+};
+
+/// How many requests from one connection can be handled at once.
+const MAX_REQUESTS_IN_FLIGHT: usize = 32;
+
+/// How many changes can be queued for a subscriber before it falls behind.
+const CHANGE_BUFFER_SIZE: usize = 256;
+
+/// A change to one of a user's tasks. Every connection with subscriptions
+/// sees every change and forwards those belonging to its own user.
+#[derive(Clone, Debug)]
+struct Change {
+    owner: i64,
+    event: TaskEvent,
+    /// The task before it was updated or removed.
+    previous: Option<Task>,
+}
+
+impl Change {
+    /// Tasks which stop matching a query are still reported,
+    /// so that subscribers know to forget them.
+    fn matches(&self, query: &TaskQuery) -> bool {
+        let current = match &self.event {
+            TaskEvent::Added(task) | TaskEvent::Updated(task) => Some(task),
+            TaskEvent::Removed(_) => None,
+        };
+
+        (current.into_iter())
+            .chain(&self.previous)
+            .any(|task| query.matches(task))
+    }
+}
+
+/// Every capability this version of the server supports.
+fn server_capabilities() -> BTreeSet<Capability> {
+    BTreeSet::new()
+}
+
+/// Handles a message on behalf of the user with the given ID.
+/// Users can only see and change their own tasks.
+fn handle_message(
+    database: &Database,
+    changes: &broadcast::Sender<Change>,
+    user_id: i64,
+    message: Message,
+) -> anyhow::Result<Response> {
+    let mut made = Vec::new();
+    let response = apply_message(database, user_id, message, &mut made)?;
+
+    for change in made {
+        // This only fails if no connection has subscriptions
+        let _ = changes.send(change);
+    }
+
+    Ok(response)
+}
+
+/// Does the work for `handle_message`, adding any changes it makes to `made`.
+/// Changes aren't published straight away since batches can be rolled back.
+fn apply_message(
+    database: &Database,
+    user_id: i64,
+    message: Message,
+    made: &mut Vec<Change>,
+) -> anyhow::Result<Response> {
+    let mut previous = None;
+
+    let (response, event) = match message {
+        Message::Add(task) => match database.add_task(user_id, task)? {
+            Ok(task) => {
+                progress_changed(database, user_id, task.parent, made)?;
+                (Response::Task(task.clone()), Some(TaskEvent::Added(task)))
+            }
+
+            Err(err) => (Response::Error(err), None),
+        },
+
+        Message::Get(id) => match database.get_task(user_id, id)? {
+            Some(task) => (Response::Task(task), None),
+            None => (Response::Error(YabuRpcError::TaskDoesntExist(id)), None),
+        },
+
+        Message::List => {
+            let tasks = database.query_tasks(user_id, &TaskQuery::default())?;
+            (Response::Tasks(tasks), None)
+        }
+
+        Message::Query(query) => (
+            Response::Tasks(database.query_tasks(user_id, &query)?),
+            None,
+        ),
+
+        Message::Update(id, mut new_task) => {
+            previous = database.get_task(user_id, id)?;
+            let dependents = database.dependents_of(user_id, id)?;
+
+            // Completing a repeating task adds its next occurrence, which takes
+            // the rule over so that completing this one again doesn't repeat it
+            let next = match &previous {
+                Some(task)
+                    if !task.complete && matches!(new_task.complete, Delta::Changed(true)) =>
+                {
+                    let mut completed = task.clone();
+                    completed.apply_delta(new_task.clone());
+
+                    if completed.recurrence.is_some() {
+                        new_task.recurrence = Delta::Changed(None);
+                    }
+
+                    completed.next_occurrence(OffsetDateTime::now_utc())
+                }
+
+                _ => None,
+            };
+
+            let updated = database.all_or_nothing(|| {
+                let (mut task, mut cascaded) = match database.update_task(user_id, id, new_task)? {
+                    Ok(updated) => updated,
+                    Err(err) => return Ok(Err(err)),
+                };
+
+                let mut added = Vec::new();
+
+                if let Some(next) = next {
+                    match database.add_task(user_id, next)? {
+                        Ok(next) => added.push(next),
+                        Err(err) => return Ok(Err(err)),
+                    }
+                }
+
+                // Repeating subtasks completed along with this task repeat too
+                let mut repeated = false;
+
+                for cascaded in &mut cascaded {
+                    let next = match cascaded.task.next_occurrence(OffsetDateTime::now_utc()) {
+                        Some(next) => next,
+                        None => continue,
+                    };
+
+                    let handed_over = TaskDelta {
+                        recurrence: Delta::Changed(None),
+                        ..TaskDelta::default()
+                    };
+
+                    let cascaded_id = cascaded.task.id_or_error()?;
+
+                    if let Err(err) = database.update_task(user_id, cascaded_id, handed_over)? {
+                        return Ok(Err(err));
+                    }
+
+                    match database.add_task(user_id, next)? {
+                        Ok(next) => added.push(next),
+                        Err(err) => return Ok(Err(err)),
+                    }
+
+                    repeated = true;
+                }
+
+                // The new occurrences change their parents' progress
+                if repeated {
+                    let fetch_again = |task: &Task| -> anyhow::Result<Task> {
+                        let task_id = task.id_or_error()?;
+                        let task = database.get_task(user_id, task_id)?;
+                        task.ok_or_else(|| {
+                            anyhow!("task {task_id} disappeared while being updated")
+                        })
+                    };
+
+                    task = fetch_again(&task)?;
+
+                    for cascaded in &mut cascaded {
+                        cascaded.task = fetch_again(&cascaded.task)?;
+                    }
+                }
+
+                Ok(Ok((task, cascaded, added)))
+            })?;
+
+            match updated {
+                Ok((task, cascaded, added)) => {
+                    made.extend(cascaded.into_iter().map(|cascaded| Change {
+                        owner: user_id,
+                        event: TaskEvent::Updated(cascaded.task),
+                        previous: Some(cascaded.previous),
+                    }));
+
+                    made.extend(added.into_iter().map(|next| Change {
+                        owner: user_id,
+                        event: TaskEvent::Added(next),
+                        previous: None,
+                    }));
+
+                    let old_parent = previous.as_ref().and_then(|previous| previous.parent);
+                    progress_changed(database, user_id, old_parent, made)?;
+
+                    if task.parent != old_parent {
+                        progress_changed(database, user_id, task.parent, made)?;
+                    }
+
+                    blocking_changed(database, user_id, dependents, made)?;
+
+                    (Response::Task(task.clone()), Some(TaskEvent::Updated(task)))
+                }
+
+                Err(err) => (Response::Error(err), None),
+            }
+        }
+
+        Message::Remove(id) => {
+            let dependents = database.dependents_of(user_id, id)?;
+            let mut removed = database.remove_task(user_id, id)?.into_iter();
+
+            match removed.next() {
+                Some(task) => {
+                    made.extend(removed.map(|subtask| Change {
+                        owner: user_id,
+                        event: TaskEvent::Removed(subtask.id.unwrap_or(id)),
+                        previous: Some(subtask),
+                    }));
+
+                    progress_changed(database, user_id, task.parent, made)?;
+                    blocking_changed(database, user_id, dependents, made)?;
+                    previous = Some(task);
+                    (Response::Nothing, Some(TaskEvent::Removed(id)))
+                }
+
+                None => (Response::Error(YabuRpcError::TaskDoesntExist(id)), None),
+            }
+        }
+
+        Message::Batch { messages, atomic } => {
+            let made_before = made.len();
+
+            let res = database.all_or_nothing(|| {
+                let mut responses = Vec::with_capacity(messages.len());
+
+                for (index, message) in messages.into_iter().enumerate() {
+                    match apply_message(database, user_id, message, made)? {
+                        Response::Error(err) if atomic => {
+                            let error = Box::new(err);
+                            return Ok(Err(YabuRpcError::BatchFailed { index, error }));
+                        }
+
+                        response => responses.push(response),
+                    }
+                }
+
+                Ok(Ok(responses))
+            })?;
+
+            match res {
+                Ok(responses) => (Response::Batch(responses), None),
+
+                Err(err) => {
+                    made.truncate(made_before);
+                    (Response::Error(err), None)
+                }
+            }
+        }
+
+        Message::AddList(list) => match database.add_list(user_id, list)? {
+            Ok(list) => (Response::List(list), None),
+            Err(err) => (Response::Error(err), None),
+        },
+
+        Message::Lists => (Response::Lists(database.lists(user_id)?), None),
+
+        Message::UpdateList(id, delta) => match database.update_list(user_id, id, delta)? {
+            Ok(list) => (Response::List(list), None),
+            Err(err) => (Response::Error(err), None),
+        },
+
+        Message::RemoveList(id) => match database.get_list(user_id, id)? {
+            Some(_) => {
+                let query = TaskQuery {
+                    list: Some(id),
+                    ..TaskQuery::default()
+                };
+
+                let made_before = made.len();
+
+                let res = database.all_or_nothing(|| {
+                    // One at a time, so that subscribers hear about each task
+                    for task in database.query_tasks(user_id, &query)? {
+                        let remove = Message::Remove(task.id_or_error()?);
+                        match apply_message(database, user_id, remove, made)? {
+                            // Subtasks which were removed along with their parent
+                            Response::Error(YabuRpcError::TaskDoesntExist(_)) => {}
+                            Response::Error(err) => return Ok(Err(err)),
+                            _ => {}
+                        }
+                    }
+
+                    database.remove_list(user_id, id)?;
+                    Ok(Ok(()))
+                })?;
+
+                match res {
+                    Ok(()) => (Response::Nothing, None),
+
+                    Err(err) => {
+                        made.truncate(made_before);
+                        (Response::Error(err), None)
+                    }
+                }
+            }
+
+            None => (Response::Error(YabuRpcError::ListDoesntExist(id)), None),
+        },
+
+        // Subscriptions belong to the connection rather than the database,
+        // so `handle_connection` deals with them outside of batches
+        Message::Subscribe(_) | Message::Unsubscribe(_) => (
+            Response::Error(YabuRpcError::InvalidRequest(
+                "subscriptions can't be batched".to_string(),
+            )),
+            None,
+        ),
+    };
+
+    if let Some(event) = event {
+        made.push(Change {
+            owner: user_id,
+            event,
+            previous,
+        });
+    }
+
+    Ok(response)
+}
+
+/// Lets subscribers know that the progress of `parent` has changed,
+/// since one of its subtasks was added, removed, or updated.
+fn progress_changed(
+    database: &Database,
+    user_id: i64,
+    parent: Option<TaskId>,
+    made: &mut Vec<Change>,
+) -> anyhow::Result<()> {
+    let parent = match parent {
+        Some(parent) => database.get_task(user_id, parent)?,
+        None => None,
+    };
+
+    made.extend(parent.map(|parent| Change {
+        owner: user_id,
+        event: TaskEvent::Updated(parent),
+        previous: None,
+    }));
+
+    Ok(())
+}
+
+/// Lets subscribers know about tasks which were blocked or unblocked since
+/// a task they depend on changed. `dependents` are as they were beforehand.
+fn blocking_changed(
+    database: &Database,
+    user_id: i64,
+    dependents: Vec<Task>,
+    made: &mut Vec<Change>,
+) -> anyhow::Result<()> {
+    for previous in dependents {
+        // Dependents which were removed along with the task are already taken care of
+        let task = match database.get_task(user_id, previous.id_or_error()?)? {
+            Some(task) if task.blocked_by != previous.blocked_by => task,
+            _ => continue,
+        };
+
+        made.push(Change {
+            owner: user_id,
+            event: TaskEvent::Updated(task),
+            previous: Some(previous),
+        });
+    }
+
+    Ok(())
+}
+
+/// Reads requests on their own task, since `Codec::read` can't be
+/// cancelled partway through a frame. The stream ends after the first error.
+///
+/// Requests are only read as far as JSON so that ones which don't make sense
+/// can still be answered, as long as they have an ID.
+fn spawn_reader<R>(mut reader: R, mut codec: Codec) -> mpsc::Receiver<Result<Value, YabuError>>
+where
+    R: AsyncRead + Send + Unpin + 'static,
+{
+    let (requests, requests_rx) = mpsc::channel(1);
+
+    tokio::spawn(async move {
+        loop {
+            let request = tokio::select! {
+                request = codec.read(&mut reader) => request,
+                // Otherwise the socket stays open after `handle_connection` gives up
+                () = requests.closed() => break,
+            };
+
+            let failed = request.is_err();
+
+            if requests.send(request).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    requests_rx
+}
+
+/// Waits for the next change, or forever if there's nothing to wait on.
+async fn next_change(
+    changes: &mut Option<broadcast::Receiver<Change>>,
+) -> Result<Change, RecvError> {
+    match changes {
+        Some(changes) => changes.recv().await,
+        None => future::pending().await,
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Send + Unpin + 'static>(
+    mut socket: S,
+    max_frame_size: u32,
+    database: DatabaseHandle,
+    changes: broadcast::Sender<Change>,
+) -> anyhow::Result<()> {
+    let mut codec = match Codec::accept(&mut socket).await {
+        Ok(codec) => codec.with_max_frame_size(max_frame_size),
+
+        Err(YabuError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+
+        Err(err) => return Err(err.into()),
+    };
+
+    let (capabilities, token) = if codec.framing() == Framing::Legacy {
+        // Legacy clients predate the handshake, so assume they
+        // don't support anything optional. They also can't authenticate.
+        (BTreeSet::new(), None)
+    } else {
+        let hello = codec.read::<Hello, _>(&mut socket).await?;
+
+        if hello.protocol_version != PROTOCOL_VERSION {
+            let response = HelloResponse::Incompatible {
+                protocol_version: PROTOCOL_VERSION,
+            };
+            codec.write(&mut socket, &response).await?;
+
+            bail!(
+                "client `{}` speaks protocol version {}, but this server speaks version {}",
+                hello.client_name,
+                hello.protocol_version,
+                PROTOCOL_VERSION,
+            );
+        }
+
+        let token = match hello.token {
+            Some(token) => {
+                database
+                    .run(move |database| database.authenticate(&token))
+                    .await??
+            }
+
+            None => None,
+        };
+
+        match &token {
+            Some(token) => eprintln!(
+                "client `{}` authenticated as `{}`",
+                hello.client_name, token.user_name
+            ),
+
+            None => eprintln!("client `{}` did not authenticate", hello.client_name),
+        }
 
-        let message = match Message::read_from_socket(&mut socket).await {
-            Ok(res) => res,
+        let capabilities: BTreeSet<Capability> = server_capabilities()
+            .intersection(&hello.capabilities)
+            .copied()
+            .collect();
+
+        let response = HelloResponse::Welcome {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: capabilities.clone(),
+            max_frame_size: codec.max_frame_size(),
+            authenticated: token.is_some(),
+        };
+        codec.write(&mut socket, &response).await?;
+
+        (capabilities, token)
+    };
+
+    if codec.framing() == Framing::Legacy {
+        return reject_legacy_messages(socket, codec).await;
+    }
+
+    let user_id = token.as_ref().map(|token| token.user_id);
+    let (reader, mut writer) = io::split(socket);
+    let mut requests = spawn_reader(reader, codec.clone());
+
+    // Only subscribed to once the client asks for changes
+    let mut changes_rx = None;
+    let mut subscriptions = HashMap::<RequestId, TaskQuery>::new();
+
+    // Requests are handled on their own tasks and answered in whatever order
+    // they finish. Once too many are in flight, reading the next one waits.
+    let in_flight = Arc::new(Semaphore::new(MAX_REQUESTS_IN_FLIGHT));
+    let (responses, mut responses_rx) = mpsc::unbounded_channel::<ServerFrame>();
+
+    loop {
+        tokio::select! {
+            request = requests.recv() => {
+                let request = match request {
+                    Some(Ok(request)) => request,
 
-            Err(YabuError::IoError(err)) => {
-                match err.kind() {
                     // `UnexpectedEof` just means the connection closed.
                     // *Probably* not worth reporting.
-                    io::ErrorKind::UnexpectedEof => return Ok(()),
-                    _ => return Err(err.into()),
+                    Some(Err(YabuError::IoError(err)))
+                        if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+
+                    Some(Err(err)) => return Err(err.into()),
+                    None => return Ok(()),
+                };
+
+                let id = (request.get("id").and_then(Value::as_u64))
+                    .and_then(|id| RequestId::try_from(id).ok());
+
+                let Request { id, message } = match (serde_json::from_value(request), id) {
+                    (Ok(request), _) => request,
+
+                    (Err(err), Some(id)) => {
+                        let response = Response::Error(YabuRpcError::InvalidRequest(err.to_string()));
+                        codec.write(&mut writer, &ServerFrame::Response { id, response }).await?;
+                        continue;
+                    }
+
+                    // There's no way to tell the client which request was wrong
+                    (Err(err), _) => bail!("client sent a request without an ID: {err}"),
+                };
+
+                let response = match (&token, message) {
+                    (Some(_), Message::Subscribe(query)) => {
+                        changes_rx.get_or_insert_with(|| changes.subscribe());
+                        subscriptions.insert(id, query);
+                        Response::Nothing
+                    }
+
+                    (Some(_), Message::Unsubscribe(subscription)) => {
+                        subscriptions.remove(&subscription);
+                        Response::Nothing
+                    }
+
+                    (Some(token), message) => {
+                        let permit = Arc::clone(&in_flight).acquire_owned().await?;
+                        let (database, changes) = (database.clone(), changes.clone());
+                        let responses = responses.clone();
+                        let (token_id, user_id) = (token.id, token.user_id);
+
+                        tokio::spawn(async move {
+                            let response = database
+                                .run(move |database| {
+                                    // Connections stay open after their user is disabled
+                                    // or their token removed, so check on every request
+                                    if !database.token_is_valid(token_id)? {
+                                        return Ok(Response::Error(YabuRpcError::Unauthorized));
+                                    }
+
+                                    handle_message(database, &changes, user_id, message)
+                                })
+                                .await
+                                .and_then(|response| response)
+                                .unwrap_or_else(|err| {
+                                    eprintln!("error while handling a request: {err:?}");
+                                    let message = err.to_string();
+                                    Response::Error(YabuRpcError::Internal { message })
+                                });
+
+                            // The receiver is only gone if the connection was dropped
+                            let _ = responses.send(ServerFrame::Response { id, response });
+                            drop(permit);
+                        });
+
+                        continue;
+                    }
+
+                    (None, _) => Response::Error(YabuRpcError::Unauthorized),
+                };
+
+                codec.write(&mut writer, &ServerFrame::Response { id, response }).await?;
+            }
+
+            Some(frame) = responses_rx.recv() => {
+                codec.write(&mut writer, &frame).await?;
+            }
+
+            change = next_change(&mut changes_rx) => {
+                let change = match change {
+                    Ok(change) if Some(change.owner) == user_id => change,
+                    Ok(_) => continue,
+
+                    Err(RecvError::Lagged(missed)) => {
+                        bail!("client fell behind and missed {missed} changes")
+                    }
+
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+
+                for (&subscription, query) in &subscriptions {
+                    if change.matches(query) {
+                        let event = change.event.clone();
+                        codec.write(&mut writer, &ServerFrame::Event { subscription, event }).await?;
+                    }
                 }
-            },
+            }
+        }
+    }
+}
+
+/// Legacy clients predate authentication and request IDs, so all that's
+/// left to do is tell them to update. Their frames are still read properly,
+/// so that they get a clear answer instead of a garbled connection.
+async fn reject_legacy_messages<S: AsyncRead + AsyncWrite + Unpin>(
+    mut socket: S,
+    mut codec: Codec,
+) -> anyhow::Result<()> {
+    // Legacy clients only know about `Nothing`, `Tasks` and `Error`, and the
+    // only error they know which holds a message is `UnknownPriority`. Today's
+    // `Response` has moved on, so the answer is spelled out by hand.
+    let response = serde_json::json!({
+        "Error": { "UnknownPriority": "(this client is too old for the server, please update it)" }
+    });
+
+    loop {
+        // Whatever they sent, the answer is the same
+        match codec.read::<Value, _>(&mut socket).await {
+            Ok(_) => codec.write(&mut socket, &response).await?,
+
+            Err(YabuError::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(())
+            }
 
             Err(err) => return Err(err.into()),
-        };
+        }
+    }
+}
+
+fn apply_migrations(database: &mut Database) -> anyhow::Result<()> {
+    for migration in database.migrate()? {
+        eprintln!(
+            "applied migration {}: {}",
+            migration.version, migration.description
+        );
+    }
+
+    Ok(())
+}
+
+fn migrate(mut database: Database, migrate_args: Migrate) -> anyhow::Result<()> {
+    let pending = database.pending_migrations()?;
+
+    if pending.is_empty() {
+        println!(
+            "the database is up to date (schema version {})",
+            database.schema_version()?
+        );
+        return Ok(());
+    }
+
+    if migrate_args.dry_run {
+        println!("would apply:");
+
+        for migration in pending {
+            println!("    {}: {}", migration.version, migration.description);
+        }
+    } else {
+        for migration in database.migrate()? {
+            println!("applied {}: {}", migration.version, migration.description);
+        }
+    }
+
+    Ok(())
+}
+
+fn manage_tokens(database: &Database, token_args: TokenCommand) -> anyhow::Result<()> {
+    match token_args.action {
+        TokenAction::Create(create_args) => {
+            let user_id = match database.user_id(&create_args.user)? {
+                Some(user_id) => user_id,
+                None => bail!("there is no user named `{}`", create_args.user),
+            };
+
+            let token = database.create_token(&create_args.name, user_id)?;
+            println!("created token `{}`: {token}", create_args.name);
+            println!("this is the only time the token will be shown");
+        }
+
+        TokenAction::Revoke(revoke_args) => {
+            if !database.revoke_token(&revoke_args.name)? {
+                bail!("there is no token named `{}`", revoke_args.name);
+            }
+        }
+
+        TokenAction::List(_) => {
+            for Token {
+                name,
+                created,
+                user_name,
+                ..
+            } in database.tokens()?
+            {
+                println!(
+                    "{name} for `{user_name}` (created {})",
+                    created.format(&DATE_TIME_FORMAT)?
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
 
-        // TODO: use a database pool instead
-        let database = Database::connect(DEFAULT_DATABASE_URL)?;
+fn manage_users(database: &Database, user_args: UserCommand) -> anyhow::Result<()> {
+    match user_args.action {
+        UserAction::Create(create_args) => {
+            database.create_user(&create_args.name)?;
+            println!("created user `{}`", create_args.name);
+        }
 
-        let response = match message {
-            Message::Add(task) => {
-                database.add_task(task)?;
-                Response::Nothing
+        UserAction::Disable(DisableUser { name }) => {
+            if !database.set_user_disabled(&name, true)? {
+                bail!("there is no user named `{name}`");
             }
+        }
 
-            Message::List => Response::Tasks(database.all_tasks()?),
-            Message::Update(id, new_task) => database.update_task(id, new_task)?,
+        UserAction::Enable(EnableUser { name }) => {
+            if !database.set_user_disabled(&name, false)? {
+                bail!("there is no user named `{name}`");
+            }
+        }
 
-            Message::Remove(id) => {
-                database.remove_task(id)?;
-                Response::Nothing
+        UserAction::List(_) => {
+            for User {
+                name,
+                disabled,
+                created,
+                ..
+            } in database.users()?
+            {
+                let disabled = if disabled { ", disabled" } else { "" };
+                println!(
+                    "{name} (created {}{disabled})",
+                    created.format(&DATE_TIME_FORMAT)?
+                );
             }
+        }
+    }
+
+    Ok(())
+}
+
+/// Handles a connection on its own task, wrapping it in TLS first if needed.
+fn spawn_connection<S>(
+    socket: S,
+    tls: Option<TlsAcceptor>,
+    max_frame_size: u32,
+    database: DatabaseHandle,
+    changes: broadcast::Sender<Change>,
+) where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    tokio::spawn(async move {
+        let res = match tls {
+            Some(tls) => match tls.accept(socket).await {
+                Ok(socket) => handle_connection(socket, max_frame_size, database, changes).await,
+                Err(err) => Err(err.into()),
+            },
+
+            None => handle_connection(socket, max_frame_size, database, changes).await,
         };
 
-        response.write_to_socket(&mut socket).await?;
+        if let Err(err) = res {
+            eprintln!("error while processing connection:");
+
+            for err in err.chain() {
+                eprintln!("    {err}")
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    path: &Path,
+    max_frame_size: u32,
+    database: DatabaseHandle,
+    changes: broadcast::Sender<Change>,
+) -> anyhow::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+    use tokio::net::UnixListener;
+
+    // A socket left behind by a previous run would stop us from binding,
+    // but one which is still being listened on belongs to another server
+    if matches!(fs::symlink_metadata(path), Ok(metadata) if metadata.file_type().is_socket()) {
+        match std::os::unix::net::UnixStream::connect(path) {
+            Ok(_) => bail!("`{}` is already in use by another server", path.display()),
+            Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+            Err(_) => {}
+        }
+    }
+
+    let listener = UnixListener::bind(path)?;
+
+    loop {
+        let (socket, _) = listener.accept().await?;
+        spawn_connection(
+            socket,
+            None,
+            max_frame_size,
+            database.clone(),
+            changes.clone(),
+        );
     }
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let args = argh::from_env::<Args>();
+#[cfg(not(unix))]
+async fn serve_unix(
+    _path: &Path,
+    _max_frame_size: u32,
+    _database: DatabaseHandle,
+    _changes: broadcast::Sender<Change>,
+) -> anyhow::Result<()> {
+    bail!("unix domain sockets aren't supported on this platform")
+}
+
+async fn serve(args: &Args, database: Database) -> anyhow::Result<()> {
+    let database = DatabaseHandle::spawn(database)?;
+    let (changes, _) = broadcast::channel(CHANGE_BUFFER_SIZE);
+    let max_frame_size = args.max_frame_size;
+
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert_file), Some(key_file)) => Some(tls::acceptor(cert_file, key_file)?),
+        (None, None) => None,
+        _ => bail!("`--tls-cert` and `--tls-key` must be used together"),
+    };
+
+    if let Some(path) = &args.unix_socket {
+        if tls.is_some() {
+            bail!("`--tls-cert` can't be used with `--unix-socket`");
+        }
+
+        return serve_unix(path, max_frame_size, database, changes).await;
+    }
+
     let listener = TcpListener::bind((args.listen_address, args.port)).await?;
 
     loop {
         let (socket, _) = listener.accept().await?;
+        spawn_connection(
+            socket,
+            tls.clone(),
+            max_frame_size,
+            database.clone(),
+            changes.clone(),
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = argh::from_env::<Args>();
+    let mut database = Database::open(&args.database)?;
 
-        tokio::spawn(async move {
-            if let Err(err) = handle_connection(socket).await {
-                eprintln!("error while processing connection:");
+    match args.subcommand {
+        Some(Subcommand::Migrate(migrate_args)) => migrate(database, migrate_args),
 
-                for err in err.chain() {
-                    eprintln!("    {err}")
-                }
+        Some(Subcommand::Token(token_args)) => {
+            apply_migrations(&mut database)?;
+            manage_tokens(&database, token_args)
+        }
+
+        Some(Subcommand::User(user_args)) => {
+            apply_migrations(&mut database)?;
+            manage_users(&database, user_args)
+        }
+
+        None => {
+            apply_migrations(&mut database)?;
+            serve(&args, database).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::{add, database, new_task};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use yabusame::{
+        codec::DEFAULT_MAX_FRAME_SIZE,
+        connection::{ClientConnection, ConnectionOptions},
+        list::List,
+        recurrence::{Frequency, Recurrence},
+    };
+
+    fn send(database: &Database, user_id: i64, message: Message) -> (Response, Vec<Change>) {
+        let mut made = Vec::new();
+        let response = apply_message(database, user_id, message, &mut made).unwrap();
+        (response, made)
+    }
+
+    /// Adds a task, then updates one which doesn't exist.
+    fn failing_batch(atomic: bool) -> Message {
+        let missing = TaskId::try_from(1000).unwrap();
+        let messages = vec![
+            Message::Add(new_task("first")),
+            Message::Update(missing, TaskDelta::default()),
+        ];
+        Message::Batch { messages, atomic }
+    }
+
+    fn all_tasks(database: &Database, user_id: i64) -> Vec<Task> {
+        database
+            .query_tasks(user_id, &TaskQuery::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn failed_atomic_batch_changes_nothing() {
+        let (database, user_id) = database();
+
+        let (response, made) = send(&database, user_id, failing_batch(true));
+        match response {
+            Response::Error(YabuRpcError::BatchFailed { index, error }) => {
+                assert_eq!(index, 1);
+                assert!(
+                    matches!(*error, YabuRpcError::TaskDoesntExist(_)),
+                    "{error}"
+                );
             }
+            response => panic!("unexpected response {response:?}"),
+        }
+
+        assert!(made.is_empty());
+        assert!(all_tasks(&database, user_id).is_empty());
+    }
+
+    #[test]
+    fn failed_non_atomic_batch_keeps_the_rest() {
+        let (database, user_id) = database();
+
+        let (response, made) = send(&database, user_id, failing_batch(false));
+        match response {
+            Response::Batch(responses) => {
+                assert!(matches!(responses[0], Response::Task(_)));
+                assert!(matches!(
+                    responses[1],
+                    Response::Error(YabuRpcError::TaskDoesntExist(_))
+                ));
+            }
+            response => panic!("unexpected response {response:?}"),
+        }
+
+        assert_eq!(made.len(), 1);
+        let tasks = all_tasks(&database, user_id);
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].description, "first");
+    }
+
+    #[test]
+    fn subtasks_completed_with_their_parent_repeat() {
+        let (database, user_id) = database();
+        // Due dates are stored to the second
+        let due_date = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+
+        let parent_id = add(&database, user_id, new_task("parent"));
+        let subtask = Task {
+            parent: Some(parent_id),
+            due_date: Some(due_date),
+            recurrence: Some(Recurrence::new(Frequency::Daily)),
+            ..new_task("subtask")
+        };
+        let subtask_id = add(&database, user_id, subtask);
+
+        let complete = TaskDelta {
+            complete: Delta::Changed(true),
+            ..TaskDelta::default()
+        };
+
+        let (response, made) = send(&database, user_id, Message::Update(parent_id, complete));
+
+        // The completed subtask handed its rule over to the next occurrence
+        let subtask = database.get_task(user_id, subtask_id).unwrap().unwrap();
+        assert!(subtask.complete);
+        assert!(subtask.recurrence.is_none());
+
+        let next = made
+            .iter()
+            .find_map(|change| match &change.event {
+                TaskEvent::Added(task) => Some(task.clone()),
+                _ => None,
+            })
+            .expect("no next occurrence was added");
+
+        assert!(!next.complete);
+        assert_eq!(next.parent, Some(parent_id));
+        assert_eq!(next.recurrence, Some(Recurrence::new(Frequency::Daily)));
+        assert_eq!(next.due_date, Some(due_date + time::Duration::days(1)));
+
+        // The parent's progress counts the new occurrence
+        match response {
+            Response::Task(task) => assert_eq!(task.progress.unwrap().total, 2),
+            response => panic!("unexpected response {response:?}"),
+        }
+    }
+
+    #[test]
+    fn removing_a_list_removes_subtasks_along_with_their_parent() {
+        let (database, user_id) = database();
+
+        let list = match send(&database, user_id, Message::AddList(List::new("garden"))).0 {
+            Response::List(list) => list.id.unwrap(),
+            response => panic!("unexpected response {response:?}"),
+        };
+
+        let parent = Task {
+            list: Some(list),
+            ..new_task("parent")
+        };
+        let parent_id = add(&database, user_id, parent);
+
+        let subtask = Task {
+            list: Some(list),
+            parent: Some(parent_id),
+            ..new_task("subtask")
+        };
+        let subtask_id = add(&database, user_id, subtask);
+
+        let (response, made) = send(&database, user_id, Message::RemoveList(list));
+        assert!(matches!(response, Response::Nothing), "{response:?}");
+        assert_eq!(made.len(), 2);
+
+        assert!(database.get_list(user_id, list).unwrap().is_none());
+        assert!(database.get_task(user_id, parent_id).unwrap().is_none());
+        assert!(database.get_task(user_id, subtask_id).unwrap().is_none());
+    }
+
+    /// `Response` as it was before the framing changed, which is all that
+    /// legacy clients can parse.
+    #[derive(Debug, serde::Deserialize)]
+    enum LegacyResponse {
+        Nothing,
+        Tasks(Vec<Value>),
+        Error(LegacyRpcError),
+    }
+
+    #[derive(Debug, serde::Deserialize)]
+    enum LegacyRpcError {
+        TaskDoesntExist(TaskId),
+        UnknownPriority(String),
+    }
+
+    #[tokio::test]
+    async fn legacy_clients_are_told_to_update() {
+        let (mut client, mut server) = io::duplex(1024);
+
+        let server = tokio::spawn(async move {
+            let codec = Codec::accept(&mut server).await.unwrap();
+            assert_eq!(codec.framing(), Framing::Legacy);
+            reject_legacy_messages(server, codec).await.unwrap();
         });
+
+        // Written out by hand, the way legacy clients frame `Message::List`
+        let message = br#""List""#;
+        client.write_u16_le(message.len() as u16).await.unwrap();
+        client.write_all(message).await.unwrap();
+
+        let length = client.read_u16_le().await.unwrap();
+        let mut response = vec![0; length.into()];
+        client.read_exact(&mut response).await.unwrap();
+
+        match serde_json::from_slice(&response).unwrap() {
+            LegacyResponse::Error(LegacyRpcError::UnknownPriority(message)) => {
+                assert!(message.contains("please update"), "{message}");
+            }
+            response => panic!("unexpected response {response:?}"),
+        }
+
+        drop(client);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn disabling_a_user_cuts_off_their_open_connections() {
+        let (database, user_id) = database();
+        let token = database.create_token("test", user_id).unwrap();
+        let database = DatabaseHandle::spawn(database).unwrap();
+
+        let (client, server) = io::duplex(1024);
+        let (changes, _) = broadcast::channel(16);
+        let server = tokio::spawn(handle_connection(
+            server,
+            DEFAULT_MAX_FRAME_SIZE,
+            database.clone(),
+            changes,
+        ));
+
+        let options = ConnectionOptions {
+            token: Some(token),
+            ..ConnectionOptions::new("test")
+        };
+        let connection = ClientConnection::from_stream(client, &options)
+            .await
+            .unwrap();
+        match connection.send(Message::List).await.unwrap() {
+            Response::Tasks(_) => {}
+            response => panic!("unexpected response {response:?}"),
+        }
+
+        let disabled = database
+            .run(|database| database.set_user_disabled("alice", true))
+            .await
+            .unwrap();
+        assert!(disabled.unwrap());
+        match connection.send(Message::List).await.unwrap() {
+            Response::Error(YabuRpcError::Unauthorized) => {}
+            response => panic!("unexpected response {response:?}"),
+        }
+
+        drop(connection);
+        server.await.unwrap().unwrap();
     }
 }
@@ -0,0 +1,21 @@
+//! Fixtures shared by the unit tests.
+
+use crate::db::Database;
+use yabusame::{Task, TaskId};
+
+/// A fresh in-memory database with one user, whose ID is also returned.
+pub fn database() -> (Database, i64) {
+    let mut database = Database::open(":memory:").unwrap();
+    database.migrate().unwrap();
+    let user_id = database.create_user("alice").unwrap();
+    (database, user_id)
+}
+
+pub fn new_task(description: &'static str) -> Task {
+    Task::new(None, false, description, Default::default(), None)
+}
+
+pub fn add(database: &Database, owner: i64, task: Task) -> TaskId {
+    let task = database.add_task(owner, task).unwrap().unwrap();
+    task.id.unwrap()
+}
@@ -0,0 +1,39 @@
+use anyhow::{bail, Context};
+use rustls_pemfile::Item;
+use std::{fs::File, io::BufReader, path::Path, sync::Arc};
+use tokio_rustls::{
+    rustls::{PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+use yabusame::tls::load_certificates;
+
+/// Returns the first private key in a PEM file.
+fn load_private_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => {
+                return Ok(PrivateKey(key))
+            }
+
+            _ => continue,
+        }
+    }
+
+    bail!("`{}` does not contain a private key", path.display())
+}
+
+pub fn acceptor(cert_file: &Path, key_file: &Path) -> anyhow::Result<TlsAcceptor> {
+    let certificates = load_certificates(cert_file)
+        .with_context(|| format!("couldn't load `{}`", cert_file.display()))?;
+    let key = load_private_key(key_file)
+        .with_context(|| format!("couldn't load `{}`", key_file.display()))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)?;
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,109 @@
+//! Serves `yabus://` with a freshly generated self-signed certificate.
+
+use std::{
+    fs, io,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+use tempfile::TempDir;
+use tokio::time;
+use yabusame::{
+    connection::{url_from_str, ClientConnection, ConnectionOptions},
+    Message, Response, YabuError, YabuRpcError,
+};
+
+/// Kills the server when the test ends, even if it fails.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// Writes a certificate for `localhost` and its key to `dir`.
+fn generate_certificate(dir: &Path) -> (PathBuf, PathBuf) {
+    let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let cert_file = dir.join("cert.pem");
+    let key_file = dir.join("key.pem");
+
+    fs::write(&cert_file, certificate.serialize_pem().unwrap()).unwrap();
+    fs::write(&key_file, certificate.serialize_private_key_pem()).unwrap();
+    (cert_file, key_file)
+}
+
+fn unused_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn start_server(port: u16, cert_file: &Path, key_file: &Path) -> Server {
+    let child = Command::new(env!("CARGO_BIN_EXE_yabuserver"))
+        .args(["-a", "127.0.0.1", "-p", &port.to_string(), "-d", ":memory:"])
+        .arg("--tls-cert")
+        .arg(cert_file)
+        .arg("--tls-key")
+        .arg(key_file)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+
+    Server(child)
+}
+
+/// Retries until the server is listening.
+async fn connect(port: u16, options: &ConnectionOptions) -> Result<ClientConnection, YabuError> {
+    let url = url_from_str(&format!("yabus://localhost:{port}")).unwrap();
+
+    for _ in 0..50 {
+        match ClientConnection::new(&url, options).await {
+            Err(YabuError::IoError(err)) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                time::sleep(Duration::from_millis(100)).await
+            }
+            result => return result,
+        }
+    }
+
+    ClientConnection::new(&url, options).await
+}
+
+#[tokio::test]
+async fn self_signed_certificate_round_trip() {
+    let dir = TempDir::new().unwrap();
+    let (cert_file, key_file) = generate_certificate(dir.path());
+    let port = unused_port();
+    let _server = start_server(port, &cert_file, &key_file);
+
+    let options = ConnectionOptions {
+        ca_file: Some(cert_file),
+        ..ConnectionOptions::new("tls-test")
+    };
+
+    let connection = connect(port, &options).await.unwrap();
+    assert!(!connection.is_authenticated());
+
+    // Answered by the server, so the request made it through both ways
+    let response = connection.send(Message::Lists).await.unwrap();
+    assert!(matches!(
+        response,
+        Response::Error(YabuRpcError::Unauthorized)
+    ));
+}
+
+#[tokio::test]
+async fn untrusted_certificate_is_rejected() {
+    let dir = TempDir::new().unwrap();
+    let (cert_file, key_file) = generate_certificate(dir.path());
+    let port = unused_port();
+    let _server = start_server(port, &cert_file, &key_file);
+
+    let result = connect(port, &ConnectionOptions::new("tls-test")).await;
+    assert!(result.is_err());
+}
@@ -0,0 +1,81 @@
+use axum::http::{header, HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+const COOKIE_NAME: &str = "flash";
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FlashKind {
+    Success,
+    Error,
+}
+
+/// A message shown once on the next page the user visits,
+/// usually after being redirected. Carried in a cookie.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct Flash {
+    kind: FlashKind,
+    message: String,
+}
+
+impl Flash {
+    pub(crate) fn success(message: impl Into<String>) -> Self {
+        Self {
+            kind: FlashKind::Success,
+            message: message.into(),
+        }
+    }
+
+    pub(crate) fn error(message: impl Into<String>) -> Self {
+        Self {
+            kind: FlashKind::Error,
+            message: message.into(),
+        }
+    }
+
+    /// Finds the flash message sent along with a request, if there is one.
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().strip_prefix(COOKIE_NAME)?.strip_prefix('='))
+            .find_map(|value| serde_urlencoded::from_str(value).ok())
+    }
+
+    /// Finds the flash message sent along with a request, and returns
+    /// headers which stop it from being shown again.
+    pub(crate) fn take(request_headers: &HeaderMap) -> (Option<Self>, HeaderMap) {
+        let flash = Self::from_headers(request_headers);
+        let mut headers = HeaderMap::new();
+
+        if flash.is_some() {
+            headers.insert(header::SET_COOKIE, Self::clear_cookie());
+        }
+
+        (flash, headers)
+    }
+
+    /// Headers which store this message for the next request.
+    pub(crate) fn set_cookie_headers(&self) -> anyhow::Result<HeaderMap> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::SET_COOKIE, self.set_cookie()?);
+        Ok(headers)
+    }
+
+    /// A `Set-Cookie` header value which stores this message.
+    fn set_cookie(&self) -> anyhow::Result<HeaderValue> {
+        // `serde_urlencoded` escapes everything that isn't allowed in a cookie
+        let value = serde_urlencoded::to_string(self)?;
+
+        Ok(HeaderValue::from_str(&format!(
+            "{COOKIE_NAME}={value}; Path=/; Max-Age=60; HttpOnly; SameSite=Lax"
+        ))?)
+    }
+
+    /// A `Set-Cookie` header value which removes the message once it's been shown.
+    fn clear_cookie() -> HeaderValue {
+        HeaderValue::from_static("flash=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax")
+    }
+}
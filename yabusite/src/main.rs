@@ -1,5 +1,9 @@
 #![feature(try_blocks)]
 
+mod flash;
+mod params;
+mod pool;
+mod relay;
 mod routes;
 mod tera_helpers;
 
@@ -10,21 +14,27 @@ use axum::{
     routing::{get, get_service},
     Router, Server,
 };
-use deadpool::unmanaged;
 use std::{
     net::{IpAddr, SocketAddr},
+    path::PathBuf,
     sync::Arc,
-    thread::available_parallelism,
+    time::Duration,
 };
 use tera::Tera;
 use tokio::{sync::RwLock, task};
 use tower_http::services::ServeDir;
 use url::Url;
-use yabusame::connection::{default_server, url_from_str, ClientConnection};
+use yabusame::connection::{default_server, url_from_str, ConnectionOptions};
 
-use crate::tera_helpers::{date_time, tera_watcher};
+use crate::{
+    pool::ConnectionPool,
+    tera_helpers::{date_time, markdown, tera_watcher},
+};
 
 const DEFAULT_YABUSITE_PORT: u16 = 8000;
+const DEFAULT_POOL_SIZE: usize = 4;
+const DEFAULT_POOL_TIMEOUT_SECS: u64 = 5;
+const CLIENT_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
 // The working directory is the workspace root in debug mode and
 // the executable directory in release mode
@@ -68,6 +78,33 @@ pub struct Args {
     )]
     pub server_url: Url,
 
+    #[argh(
+        option,
+        short = 't',
+        description = "API token to authenticate with (create one with `yabuserver token create`)"
+    )]
+    pub token: Option<String>,
+
+    #[argh(
+        option,
+        description = "PEM file with extra certificates to trust for `yabus://` servers"
+    )]
+    pub ca_file: Option<PathBuf>,
+
+    #[argh(
+        option,
+        description = "number of connections to `yabuserver` to share between requests",
+        default = "DEFAULT_POOL_SIZE"
+    )]
+    pool_size: usize,
+
+    #[argh(
+        option,
+        description = "seconds to wait for a connection to `yabuserver` before giving up",
+        default = "DEFAULT_POOL_TIMEOUT_SECS"
+    )]
+    pool_timeout: u64,
+
     #[argh(
         option,
         short = 'a',
@@ -89,14 +126,20 @@ pub struct Args {
 async fn main() {
     let args = argh::from_env::<Args>();
 
-    let parallelism = available_parallelism().unwrap().get();
-    let mut yabuserver_connections = Vec::with_capacity(parallelism);
+    let options = ConnectionOptions {
+        token: args.token.clone(),
+        ca_file: args.ca_file.clone(),
+        ..ConnectionOptions::new(CLIENT_NAME)
+    };
 
-    for _ in 0..parallelism {
-        yabuserver_connections.push(ClientConnection::new(&args.server_url).await.unwrap());
-    }
+    let relay = relay::spawn(args.server_url.clone(), options.clone());
 
-    let connection_pool = unmanaged::Pool::from(yabuserver_connections);
+    let connection_pool = ConnectionPool::new(
+        args.server_url,
+        options,
+        args.pool_size,
+        Duration::from_secs(args.pool_timeout),
+    );
 
     let static_files = get_service(ServeDir::new(STATIC_DIR)).handle_error(|err| async move {
         eprintln!("error while serving a static file: {err}");
@@ -105,12 +148,35 @@ async fn main() {
 
     let tera = Arc::new(RwLock::new(Tera::new(TEMPLATE_GLOB).unwrap()));
     tera.write().await.register_filter("date_time", date_time);
+    tera.write().await.register_filter("markdown", markdown);
 
     let app = Router::new()
-        .route("/", get(routes::index))
+        .route("/", get(routes::index).post(routes::update_tasks))
+        .route(
+            "/tasks/new",
+            get(routes::tasks::new_form).post(routes::tasks::create),
+        )
+        .route("/tasks/:id", get(routes::tasks::show))
+        .route(
+            "/tasks/:id/edit",
+            get(routes::tasks::edit_form).post(routes::tasks::update),
+        )
+        .route(
+            "/api/tasks",
+            get(routes::api::list).post(routes::api::create),
+        )
+        .route(
+            "/api/tasks/:id",
+            get(routes::api::show)
+                .patch(routes::api::update)
+                .delete(routes::api::delete),
+        )
+        .route("/api/openapi.json", get(routes::api::openapi))
+        .route("/events", get(routes::events::subscribe))
         .nest("/static", static_files)
         .layer(Extension(Arc::clone(&tera)))
-        .layer(Extension(connection_pool));
+        .layer(Extension(connection_pool))
+        .layer(Extension(relay));
 
     if cfg!(debug_assertions) {
         task::spawn(tera_watcher(tera, TEMPLATE_DIR));
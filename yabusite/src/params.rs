@@ -0,0 +1,32 @@
+//! Helpers for (de)serializing query strings and forms.
+
+use serde::{de, Deserialize, Deserializer, Serializer};
+use std::{fmt::Display, str::FromStr};
+
+/// Deserializes an optional value using its `FromStr` implementation.
+/// HTML forms send empty strings for blank fields, so these become `None`.
+pub(crate) fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let maybe_str = Option::<String>::deserialize(deserializer)?;
+
+    match maybe_str.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(s) => T::from_str(s).map(Some).map_err(de::Error::custom),
+    }
+}
+
+/// The inverse of `empty_as_none`.
+pub(crate) fn display<S, T>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Display,
+{
+    match value {
+        Some(value) => serializer.collect_str(value),
+        None => serializer.serialize_none(),
+    }
+}
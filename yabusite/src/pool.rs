@@ -0,0 +1,106 @@
+//! A few connections to `yabuserver`, shared by every request. Connections
+//! which break, such as when `yabuserver` is restarted, are replaced.
+
+use anyhow::anyhow;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::Mutex, time};
+use url::Url;
+use yabusame::{
+    connection::{ClientConnection, ConnectionOptions},
+    Message, YabuError,
+};
+
+/// Cheaply cloneable.
+#[derive(Clone)]
+pub(crate) struct ConnectionPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    server_url: Url,
+    options: ConnectionOptions,
+    connect_timeout: Duration,
+    /// Empty until first used.
+    slots: Vec<Mutex<Option<ClientConnection>>>,
+    next_slot: AtomicUsize,
+}
+
+impl ConnectionPool {
+    /// Connections are only opened once they're needed, so this succeeds
+    /// even if `yabuserver` isn't running yet.
+    pub(crate) fn new(
+        server_url: Url,
+        options: ConnectionOptions,
+        size: usize,
+        connect_timeout: Duration,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                server_url,
+                options,
+                connect_timeout,
+                slots: (0..size.max(1)).map(|_| Mutex::new(None)).collect(),
+                next_slot: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Takes turns between connections, opening a new one
+    /// if the one whose turn it is has broken.
+    pub(crate) async fn get(&self) -> anyhow::Result<ClientConnection> {
+        let inner = &self.inner;
+        let index = inner.next_slot.fetch_add(1, Ordering::Relaxed) % inner.slots.len();
+
+        // Held while connecting so that concurrent requests
+        // don't each open their own replacement
+        let mut slot = inner.slots[index].lock().await;
+
+        if let Some(connection) = slot.as_ref().filter(|connection| !connection.is_broken()) {
+            return Ok(connection.clone());
+        }
+
+        let connection = time::timeout(
+            inner.connect_timeout,
+            ClientConnection::new(&inner.server_url, &inner.options),
+        )
+        .await
+        .map_err(|_| anyhow!("timed out connecting to yabuserver"))??;
+
+        *slot = Some(connection.clone());
+        Ok(connection)
+    }
+
+    /// Sends a message over one of the connections. If the connection turns
+    /// out to be closed, the message is retried once over a fresh connection,
+    /// as long as that can't apply it twice.
+    pub(crate) async fn send(&self, message: Message) -> anyhow::Result<yabusame::Response> {
+        match self.get().await?.send(message.clone()).await {
+            Err(YabuError::NotSent) => {
+                eprintln!("lost a connection to yabuserver, retrying");
+                Ok(self.get().await?.send(message).await?)
+            }
+
+            // The server might have handled the message before the connection
+            // closed, so only messages which don't change anything are resent
+            Err(YabuError::ConnectionClosed) if is_read_only(&message) => {
+                eprintln!("lost a connection to yabuserver, retrying");
+                Ok(self.get().await?.send(message).await?)
+            }
+
+            result => Ok(result?),
+        }
+    }
+}
+
+fn is_read_only(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::Get(_) | Message::List | Message::Query(_) | Message::Lists
+    )
+}
@@ -0,0 +1,57 @@
+//! Relays changes to tasks from `yabuserver` to every open page.
+
+use futures_util::StreamExt;
+use std::time::Duration;
+use tokio::{sync::broadcast, time};
+use url::Url;
+use yabusame::{
+    connection::{ClientConnection, ConnectionOptions},
+    TaskEvent, TaskQuery,
+};
+
+/// How many changes can be queued for a page before it falls behind.
+const RELAY_BUFFER_SIZE: usize = 64;
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+pub(crate) enum Relayed {
+    Changed(Box<TaskEvent>),
+    /// Changes might have been missed, so pages should reload.
+    Resync,
+}
+
+pub(crate) type Relay = broadcast::Sender<Relayed>;
+
+/// Subscribes to changes on its own connection, resubscribing whenever
+/// the connection is lost.
+pub(crate) fn spawn(server_url: Url, options: ConnectionOptions) -> Relay {
+    let (relay, _) = broadcast::channel(RELAY_BUFFER_SIZE);
+    tokio::spawn(run(server_url, options, relay.clone()));
+    relay
+}
+
+async fn run(server_url: Url, options: ConnectionOptions, relay: Relay) {
+    loop {
+        let subscription = match ClientConnection::new(&server_url, &options).await {
+            Ok(connection) => connection.subscribe(TaskQuery::default()).await,
+            Err(err) => Err(err),
+        };
+
+        match subscription {
+            Ok(mut subscription) => {
+                // Sending only fails if no pages are open
+                let _ = relay.send(Relayed::Resync);
+
+                while let Some(event) = subscription.next().await {
+                    let _ = relay.send(Relayed::Changed(Box::new(event)));
+                }
+
+                eprintln!("lost the subscription to yabuserver");
+            }
+
+            Err(err) => eprintln!("couldn't subscribe to changes from yabuserver: {err}"),
+        }
+
+        time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
@@ -0,0 +1,424 @@
+//! A JSON API for scripts, mirroring `yabusame::Message`.
+//! The schema is served at `/api/openapi.json`.
+
+use anyhow::anyhow;
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{FromRequest, Path, Query, RequestParts},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use axum_macros::debug_handler;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, str::FromStr};
+use time::OffsetDateTime;
+use utoipa::{OpenApi, ToSchema};
+use yabusame::{
+    list::ListId, recurrence::Recurrence, Delta, Message, Priority, Task, TaskDelta, TaskId,
+    YabuError, YabuRpcError,
+};
+
+use super::{internal_error, query_tasks, IndexQuery};
+use crate::pool::ConnectionPool;
+use delta_schema::{ApiDateTime, ApiFrequency, ApiPriority, ApiRecurrence, ApiTaskDelta};
+
+mod delta_schema;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(list, create, show, update, delete),
+    components(schemas(
+        ApiTask,
+        ApiProgress,
+        NewTask,
+        ApiTaskDelta,
+        ApiPriority,
+        ApiDateTime,
+        ApiRecurrence,
+        ApiFrequency,
+        ErrorBody
+    ))
+)]
+pub(crate) struct ApiDoc;
+
+#[debug_handler]
+pub(crate) async fn openapi() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// The body of every error response.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ErrorBody {
+    error: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            error: self.message,
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        let status = internal_error("handling an API request")(err);
+        Self::new(status, "internal server error")
+    }
+}
+
+fn rpc_error_status(err: &YabuRpcError) -> StatusCode {
+    match err {
+        YabuRpcError::TaskDoesntExist(_) => StatusCode::NOT_FOUND,
+        // A task referred to a list which doesn't exist
+        YabuRpcError::ListDoesntExist(_)
+        | YabuRpcError::ListNameTaken(_)
+        | YabuRpcError::InvalidListName(_)
+        | YabuRpcError::UnknownPriority(_)
+        | YabuRpcError::InvalidTag(_)
+        | YabuRpcError::InvalidRequest(_)
+        | YabuRpcError::SubtaskCycle { .. }
+        | YabuRpcError::DependencyCycle { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        // yabusite's own token was rejected, which isn't the client's fault
+        YabuRpcError::Unauthorized | YabuRpcError::Internal { .. } => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+        YabuRpcError::BatchFailed { error, .. } => rpc_error_status(error),
+    }
+}
+
+impl From<YabuRpcError> for ApiError {
+    fn from(err: YabuRpcError) -> Self {
+        Self::new(rpc_error_status(&err), err.to_string())
+    }
+}
+
+type ApiResult<T> = Result<T, ApiError>;
+
+/// Wraps another extractor, such as `Json` or `Path`, so that its rejections
+/// are answered with an `ErrorBody` like every other error.
+pub(crate) struct Api<E>(E);
+
+#[async_trait]
+impl<B, E> FromRequest<B> for Api<E>
+where
+    B: Send,
+    E: FromRequest<B>,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let rejection = match E::from_request(req).await {
+            Ok(extracted) => return Ok(Self(extracted)),
+            Err(rejection) => rejection.into_response(),
+        };
+
+        // Rejections only explain what went wrong in their plain text body
+        let status = rejection.status();
+        let mut body = rejection.into_body();
+        let mut message = Vec::new();
+
+        while let Some(chunk) = body.data().await {
+            message.extend_from_slice(&chunk.map_err(anyhow::Error::from)?);
+        }
+
+        Err(ApiError::new(status, String::from_utf8_lossy(&message)))
+    }
+}
+
+fn parse_priority(priority: &str) -> ApiResult<Priority> {
+    Priority::from_str(priority).map_err(|err| match err {
+        YabuError::UnknownPriority(_) => {
+            ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, err.to_string())
+        }
+        err => anyhow::Error::from(err).into(),
+    })
+}
+
+fn parse_recurrence(recurrence: &str) -> ApiResult<Recurrence> {
+    Recurrence::from_str(recurrence)
+        .map_err(|err| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, err.to_string()))
+}
+
+/// For messages which answer with the task they affected.
+fn expect_task(response: yabusame::Response) -> ApiResult<ApiTask> {
+    match response {
+        yabusame::Response::Task(task) => Ok(ApiTask::try_from(task).map_err(anyhow::Error::from)?),
+        yabusame::Response::Error(err) => Err(err.into()),
+        response => Err(anyhow!("unexpected response {response:?}").into()),
+    }
+}
+
+/// For messages which only answer with errors.
+fn expect_nothing(response: yabusame::Response) -> ApiResult<()> {
+    match response {
+        yabusame::Response::Nothing => Ok(()),
+        yabusame::Response::Error(err) => Err(err.into()),
+        response => Err(anyhow!("unexpected response {response:?}").into()),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ApiTask {
+    id: u32,
+    complete: bool,
+    description: String,
+    /// `lowest`, `low`, `medium`, `high`, or `critical`
+    priority: String,
+    #[serde(with = "time::serde::rfc3339::option")]
+    due_date: Option<OffsetDateTime>,
+    tags: BTreeSet<String>,
+    /// How often the task repeats once it's completed, such as `week on mon`
+    every: Option<String>,
+    /// The ID of the task this is a subtask of
+    parent: Option<u32>,
+    /// How many of the task's subtasks are complete, if it has any
+    progress: Option<ApiProgress>,
+    /// The IDs of the tasks which have to be completed before this one can start
+    depends_on: BTreeSet<u32>,
+    /// The tasks in `depends_on` which aren't complete yet
+    blocked_by: BTreeSet<u32>,
+    /// The ID of the list the task is in
+    list: Option<u32>,
+    /// Longer notes about the task, in Markdown
+    notes: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ApiProgress {
+    complete: u32,
+    total: u32,
+}
+
+impl TryFrom<Task> for ApiTask {
+    type Error = YabuError;
+
+    fn try_from(task: Task) -> Result<Self, Self::Error> {
+        Ok(Self {
+            id: task.id_or_error()?.0.get(),
+            complete: task.complete,
+            description: task.description.into_owned(),
+            priority: task.priority.as_str().to_string(),
+            due_date: task.due_date,
+            tags: task.tags,
+            every: task.recurrence.map(|recurrence| recurrence.to_string()),
+            parent: task.parent.map(|parent| parent.0.get()),
+            progress: task.progress.map(|progress| ApiProgress {
+                complete: progress.complete,
+                total: progress.total,
+            }),
+            depends_on: task
+                .depends_on
+                .iter()
+                .map(|task_id| task_id.0.get())
+                .collect(),
+            blocked_by: task
+                .blocked_by
+                .iter()
+                .map(|task_id| task_id.0.get())
+                .collect(),
+            list: task.list.map(|list| list.0.get()),
+            notes: task.notes,
+        })
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct NewTask {
+    description: String,
+    #[serde(default)]
+    complete: bool,
+    /// `lowest`, `low`, `medium` (the default), `high`, or `critical`
+    priority: Option<String>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    due_date: Option<OffsetDateTime>,
+    #[serde(default)]
+    tags: BTreeSet<String>,
+    /// Such as `day`, `2 weeks`, or `week on mon,thu`
+    every: Option<String>,
+    /// The ID of the task to make this a subtask of
+    #[schema(value_type = Option<u32>)]
+    parent: Option<TaskId>,
+    /// The IDs of the tasks which have to be completed before this one can start
+    #[serde(default)]
+    #[schema(value_type = Vec<u32>)]
+    depends_on: BTreeSet<TaskId>,
+    /// The ID of the list to add the task to
+    #[schema(value_type = Option<u32>)]
+    list: Option<ListId>,
+    /// Longer notes about the task, in Markdown
+    notes: Option<String>,
+}
+
+/// Lists tasks, taking the same filters as the index page.
+#[utoipa::path(
+    get,
+    path = "/api/tasks",
+    params(IndexQuery),
+    responses(
+        (status = 200, description = "The matching tasks", body = [ApiTask]),
+        (status = 400, description = "The query is invalid", body = ErrorBody),
+        (status = 422, description = "The query can't be parsed", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub(crate) async fn list(
+    Api(Query(query)): Api<Query<IndexQuery>>,
+    connection_pool: Extension<ConnectionPool>,
+) -> ApiResult<Json<Vec<ApiTask>>> {
+    let task_query = query
+        .task_query()
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err.to_string()))?;
+
+    let tasks = query_tasks(&connection_pool, task_query)
+        .await?
+        .into_iter()
+        .map(ApiTask::try_from)
+        .collect::<Result<_, _>>()
+        .map_err(anyhow::Error::from)?;
+
+    Ok(Json(tasks))
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/tasks",
+    request_body = NewTask,
+    responses(
+        (status = 201, description = "The task was created", body = ApiTask),
+        (status = 400, description = "The body isn't JSON", body = ErrorBody),
+        (status = 422, description = "The task is invalid", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub(crate) async fn create(
+    connection_pool: Extension<ConnectionPool>,
+    Api(Json(new_task)): Api<Json<NewTask>>,
+) -> ApiResult<(StatusCode, Json<ApiTask>)> {
+    if new_task.description.trim().is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "tasks need a description",
+        ));
+    }
+
+    let priority = match &new_task.priority {
+        Some(priority) => parse_priority(priority)?,
+        None => Priority::Medium,
+    };
+
+    let task = Task {
+        tags: new_task.tags,
+        recurrence: new_task
+            .every
+            .as_deref()
+            .map(parse_recurrence)
+            .transpose()?,
+        parent: new_task.parent,
+        depends_on: new_task.depends_on,
+        list: new_task.list,
+        notes: new_task.notes,
+        ..Task::new(
+            None,
+            new_task.complete,
+            new_task.description,
+            priority,
+            new_task.due_date,
+        )
+    };
+
+    let response = connection_pool.send(Message::Add(task)).await?;
+    Ok((StatusCode::CREATED, Json(expect_task(response)?)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/tasks/{id}",
+    params(("id" = u32, Path, description = "The task's ID")),
+    responses(
+        (status = 200, description = "The task", body = ApiTask),
+        (status = 400, description = "The ID isn't valid", body = ErrorBody),
+        (status = 404, description = "There is no such task", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub(crate) async fn show(
+    Api(Path(task_id)): Api<Path<TaskId>>,
+    connection_pool: Extension<ConnectionPool>,
+) -> ApiResult<Json<ApiTask>> {
+    let response = connection_pool.send(Message::Get(task_id)).await?;
+    Ok(Json(expect_task(response)?))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/tasks/{id}",
+    params(("id" = u32, Path, description = "The task's ID")),
+    request_body = ApiTaskDelta,
+    responses(
+        (status = 200, description = "The task after the update", body = ApiTask),
+        (status = 400, description = "The ID isn't valid, or the body isn't JSON", body = ErrorBody),
+        (status = 404, description = "There is no such task", body = ErrorBody),
+        (status = 422, description = "The changes are invalid", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub(crate) async fn update(
+    Api(Path(task_id)): Api<Path<TaskId>>,
+    connection_pool: Extension<ConnectionPool>,
+    Api(Json(delta)): Api<Json<TaskDelta>>,
+) -> ApiResult<Json<ApiTask>> {
+    if matches!(&delta.description, Delta::Changed(description) if description.trim().is_empty()) {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "tasks need a description",
+        ));
+    }
+
+    let response = connection_pool
+        .send(Message::Update(task_id, delta))
+        .await?;
+
+    Ok(Json(expect_task(response)?))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/tasks/{id}",
+    params(("id" = u32, Path, description = "The task's ID")),
+    responses(
+        (status = 204, description = "The task was deleted"),
+        (status = 400, description = "The ID isn't valid", body = ErrorBody),
+        (status = 404, description = "There is no such task", body = ErrorBody),
+    )
+)]
+#[debug_handler]
+pub(crate) async fn delete(
+    Api(Path(task_id)): Api<Path<TaskId>>,
+    connection_pool: Extension<ConnectionPool>,
+) -> ApiResult<StatusCode> {
+    let response = connection_pool.send(Message::Remove(task_id)).await?;
+
+    expect_nothing(response)?;
+    Ok(StatusCode::NO_CONTENT)
+}
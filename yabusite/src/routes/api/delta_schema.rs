@@ -0,0 +1,186 @@
+//! Describes `yabusame::TaskDelta` in the OpenAPI document. PATCH bodies are
+//! deserialized straight into a `TaskDelta`, so that clients built on
+//! `yabusame` can send theirs as is, and these types are never constructed.
+
+#![allow(dead_code)]
+
+use utoipa::{
+    openapi::{
+        ArrayBuilder, KnownFormat, ObjectBuilder, OneOfBuilder, Ref, RefOr, Schema, SchemaFormat,
+        SchemaType,
+    },
+    ToSchema,
+};
+
+/// `yabusame::TaskDelta`, which is what PATCH bodies are.
+/// Fields which are left out aren't changed.
+#[derive(ToSchema)]
+#[schema(title = "TaskDelta")]
+pub(crate) struct ApiTaskDelta {
+    #[schema(schema_with = complete)]
+    complete: (),
+    #[schema(schema_with = description)]
+    description: (),
+    #[schema(schema_with = priority)]
+    priority: (),
+    #[schema(schema_with = due_date)]
+    due_date: (),
+    #[schema(schema_with = tags)]
+    tags: (),
+    #[schema(schema_with = recurrence)]
+    recurrence: (),
+    #[schema(schema_with = parent)]
+    parent: (),
+    #[schema(schema_with = depends_on)]
+    depends_on: (),
+    #[schema(schema_with = list)]
+    list: (),
+    #[schema(schema_with = notes)]
+    notes: (),
+}
+
+/// `yabusame::Priority`.
+#[derive(ToSchema)]
+#[schema(title = "Priority")]
+pub(crate) enum ApiPriority {
+    Lowest,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+/// How `time` serializes dates: `[year, day of the year, hour, minute,
+/// second, nanosecond, offset hours, offset minutes, offset seconds]`.
+#[derive(ToSchema)]
+#[schema(title = "DateTime")]
+pub(crate) struct ApiDateTime(Vec<i32>);
+
+/// `yabusame::recurrence::Recurrence`.
+#[derive(ToSchema)]
+#[schema(title = "Recurrence")]
+pub(crate) struct ApiRecurrence {
+    frequency: ApiFrequency,
+    /// At most 1000
+    interval: u32,
+    /// Only for `Weekly`. From 1 for Monday to 7 for Sunday
+    #[schema(default)]
+    weekdays: Vec<u8>,
+    /// No occurrences are added which would be due after this
+    until: Option<ApiDateTime>,
+    /// How many occurrences are left, counting the current one
+    count: Option<u32>,
+}
+
+#[derive(ToSchema)]
+#[schema(title = "Frequency")]
+pub(crate) enum ApiFrequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+fn of_type(schema_type: SchemaType) -> ObjectBuilder {
+    ObjectBuilder::new().schema_type(schema_type)
+}
+
+/// `yabusame::Delta`: either `"Unchanged"`, or `{"Changed": value}`.
+fn delta(value: impl Into<RefOr<Schema>>, description: &str) -> RefOr<Schema> {
+    let unchanged = of_type(SchemaType::String).enum_values(Some(["Unchanged"]));
+    let changed = ObjectBuilder::new()
+        .property("Changed", value)
+        .required("Changed");
+
+    let delta = OneOfBuilder::new()
+        .item(unchanged)
+        .item(changed)
+        .description(Some(description));
+
+    Schema::OneOf(delta.build()).into()
+}
+
+/// `yabusame::SetDelta`s, which are applied in order: `{"Add": value}`,
+/// `{"Remove": value}`, or `{"Replace": [values]}`.
+fn set_deltas(value: impl Into<RefOr<Schema>>, description: &str) -> RefOr<Schema> {
+    let value = value.into();
+    let variant =
+        |name, value: RefOr<Schema>| ObjectBuilder::new().property(name, value).required(name);
+    let values = Schema::Array(ArrayBuilder::new().items(value.clone()).build());
+
+    let set_delta = OneOfBuilder::new()
+        .item(variant("Add", value.clone()))
+        .item(variant("Remove", value))
+        .item(variant("Replace", values.into()));
+
+    let set_deltas = ArrayBuilder::new()
+        .items(set_delta)
+        .description(Some(description));
+
+    Schema::Array(set_deltas.build()).into()
+}
+
+fn id() -> ObjectBuilder {
+    of_type(SchemaType::Integer).format(Some(SchemaFormat::KnownFormat(KnownFormat::Int32)))
+}
+
+fn complete() -> RefOr<Schema> {
+    delta(of_type(SchemaType::Boolean), "Whether the task is complete")
+}
+
+fn description() -> RefOr<Schema> {
+    delta(of_type(SchemaType::String), "Can't be empty")
+}
+
+fn priority() -> RefOr<Schema> {
+    delta(
+        Ref::from_schema_name("ApiPriority"),
+        "How urgent the task is",
+    )
+}
+
+fn due_date() -> RefOr<Schema> {
+    delta(
+        Ref::from_schema_name("ApiDateTime"),
+        "`null` removes the due date",
+    )
+}
+
+fn tags() -> RefOr<Schema> {
+    set_deltas(of_type(SchemaType::String), "Applied in order")
+}
+
+fn recurrence() -> RefOr<Schema> {
+    delta(
+        Ref::from_schema_name("ApiRecurrence"),
+        "`null` stops the task from repeating",
+    )
+}
+
+fn parent() -> RefOr<Schema> {
+    delta(
+        id(),
+        "The ID of the task to make this a subtask of. `null` makes it top-level",
+    )
+}
+
+fn depends_on() -> RefOr<Schema> {
+    set_deltas(
+        id(),
+        "The IDs of the tasks which have to be completed before this one can start, \
+        applied in order",
+    )
+}
+
+fn list() -> RefOr<Schema> {
+    delta(
+        id(),
+        "The ID of the list to move the task to. `null` takes it out of its list",
+    )
+}
+
+fn notes() -> RefOr<Schema> {
+    delta(
+        of_type(SchemaType::String),
+        "Longer notes about the task, in Markdown. `null` removes them",
+    )
+}
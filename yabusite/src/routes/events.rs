@@ -0,0 +1,88 @@
+//! `/events`, which streams changes to the index page's script.
+
+use axum::{
+    response::sse::{Event, KeepAlive, Sse},
+    Extension,
+};
+use axum_macros::debug_handler;
+use futures_util::{stream, Stream};
+use serde::Serialize;
+use std::collections::BTreeSet;
+use tokio::sync::broadcast::error::RecvError;
+use yabusame::{Priority, Progress, Task, TaskEvent, TaskId, DATE_TIME_FORMAT};
+
+use crate::relay::{Relay, Relayed};
+
+/// A task as the index table shows it.
+#[derive(Serialize)]
+struct TaskRow {
+    id: TaskId,
+    complete: bool,
+    description: String,
+    priority: Priority,
+    due_date: Option<String>,
+    tags: BTreeSet<String>,
+    parent: Option<TaskId>,
+    progress: Option<Progress>,
+    blocked_by: BTreeSet<TaskId>,
+}
+
+impl TryFrom<Task> for TaskRow {
+    type Error = anyhow::Error;
+
+    fn try_from(task: Task) -> anyhow::Result<Self> {
+        Ok(Self {
+            id: task.id_or_error()?,
+            complete: task.complete,
+            description: task.description.into_owned(),
+            priority: task.priority,
+            due_date: task
+                .due_date
+                .map(|due_date| due_date.format(&DATE_TIME_FORMAT))
+                .transpose()?,
+            tags: task.tags,
+            parent: task.parent,
+            progress: task.progress,
+            blocked_by: task.blocked_by,
+        })
+    }
+}
+
+fn to_event(relayed: Relayed) -> anyhow::Result<Event> {
+    let event = match relayed {
+        Relayed::Changed(event) => match *event {
+            TaskEvent::Added(task) => Event::default()
+                .event("added")
+                .json_data(TaskRow::try_from(task)?)?,
+
+            TaskEvent::Updated(task) => Event::default()
+                .event("updated")
+                .json_data(TaskRow::try_from(task)?)?,
+
+            TaskEvent::Removed(id) => Event::default().event("removed").data(id.to_string()),
+        },
+
+        // Browsers ignore events without data
+        Relayed::Resync => Event::default().event("resync").data("resync"),
+    };
+
+    Ok(event)
+}
+
+#[debug_handler]
+pub(crate) async fn subscribe(
+    relay: Extension<Relay>,
+) -> Sse<impl Stream<Item = anyhow::Result<Event>>> {
+    let events = stream::unfold(relay.subscribe(), |mut receiver| async move {
+        let relayed = match receiver.recv().await {
+            Ok(relayed) => relayed,
+            // The page can't be told what it missed, so have it reload
+            Err(RecvError::Lagged(_)) => Relayed::Resync,
+            Err(RecvError::Closed) => return None,
+        };
+
+        Some((to_event(relayed), receiver))
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,429 @@
+use anyhow::anyhow;
+use axum::{
+    extract::{Form, Query},
+    http::{HeaderMap, StatusCode, Uri},
+    response::{Html, Redirect},
+    Extension,
+};
+use axum_macros::debug_handler;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Not,
+    str::FromStr,
+    sync::Arc,
+};
+use tera::Tera;
+use time::{format_description, Date, OffsetDateTime};
+use tokio::sync::RwLock;
+use utoipa::IntoParams;
+use yabusame::{
+    list::{List, ListId},
+    nest_subtasks, Delta, Message, Priority, Sort, SortKey, Task, TaskDelta, TaskId, TaskQuery,
+    YabuRpcError,
+};
+
+pub(crate) mod api;
+pub(crate) mod events;
+pub(crate) mod tasks;
+
+use crate::{
+    flash::Flash,
+    params::{display, empty_as_none},
+    pool::ConnectionPool,
+    tera_helpers::axum_render,
+};
+
+/// Logs an error, then reports it to the user as a 500.
+fn internal_error(action: &str) -> impl FnOnce(anyhow::Error) -> StatusCode + '_ {
+    move |err| {
+        eprintln!("error while {action}:");
+        for err in err.chain() {
+            eprintln!("    {err}");
+        }
+
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+}
+
+async fn query_tasks(
+    connection_pool: &ConnectionPool,
+    query: TaskQuery,
+) -> anyhow::Result<Vec<Task>> {
+    match connection_pool.send(Message::Query(query)).await? {
+        // `yabusame::Response` is qualified to avoid confusion with `http::Response`
+        yabusame::Response::Tasks(tasks) => Ok(tasks),
+        yabusame::Response::Error(err) => Err(err.into()),
+        response => Err(anyhow!("unexpected response {response:?}")),
+    }
+}
+
+async fn fetch_task(
+    connection_pool: &ConnectionPool,
+    task_id: TaskId,
+) -> anyhow::Result<Option<Task>> {
+    match connection_pool.send(Message::Get(task_id)).await? {
+        yabusame::Response::Task(task) => Ok(Some(task)),
+        yabusame::Response::Error(YabuRpcError::TaskDoesntExist(_)) => Ok(None),
+        yabusame::Response::Error(err) => Err(err.into()),
+        response => Err(anyhow!("unexpected response {response:?}")),
+    }
+}
+
+/// Returns every one of the user's lists, including archived ones.
+async fn fetch_lists(connection_pool: &ConnectionPool) -> anyhow::Result<Vec<List>> {
+    match connection_pool.send(Message::Lists).await? {
+        yabusame::Response::Lists(lists) => Ok(lists),
+        yabusame::Response::Error(err) => Err(err.into()),
+        response => Err(anyhow!("unexpected response {response:?}")),
+    }
+}
+
+/// Treats dates as starting at midnight UTC.
+fn date_from_str(s: &str) -> anyhow::Result<OffsetDateTime> {
+    let date_fmt = format_description::parse("[year]-[month]-[day]")?;
+    Ok(Date::parse(s, &date_fmt)?.midnight().assume_utc())
+}
+
+/// Used by both the index page and `GET /api/tasks`.
+#[derive(Clone, Default, Deserialize, IntoParams, Serialize)]
+#[into_params(parameter_in = Query)]
+#[serde(default)]
+pub(crate) struct IndexQuery {
+    /// Only show complete (or incomplete) tasks
+    #[serde(deserialize_with = "empty_as_none", serialize_with = "display")]
+    complete: Option<bool>,
+    /// `lowest`, `low`, `medium`, `high`, or `critical`
+    #[serde(deserialize_with = "empty_as_none", serialize_with = "display")]
+    #[param(value_type = Option<String>)]
+    min_priority: Option<Priority>,
+    /// `lowest`, `low`, `medium`, `high`, or `critical`
+    #[serde(deserialize_with = "empty_as_none", serialize_with = "display")]
+    #[param(value_type = Option<String>)]
+    max_priority: Option<Priority>,
+    /// `YYYY-MM-DD`
+    #[serde(deserialize_with = "empty_as_none")]
+    due_before: Option<String>,
+    /// `YYYY-MM-DD`
+    #[serde(deserialize_with = "empty_as_none")]
+    due_after: Option<String>,
+    /// Only show incomplete tasks which are past their due date
+    #[serde(skip_serializing_if = "Not::not")]
+    overdue: bool,
+    /// Only show tasks whose description contains this text
+    #[serde(deserialize_with = "empty_as_none")]
+    q: Option<String>,
+    /// Only show tasks with this tag
+    #[serde(deserialize_with = "empty_as_none")]
+    tag: Option<String>,
+    /// Only show incomplete tasks which aren't waiting on any others
+    #[serde(skip_serializing_if = "Not::not")]
+    ready: bool,
+    /// Only show tasks in the list with this ID
+    #[serde(deserialize_with = "empty_as_none", serialize_with = "display")]
+    #[param(value_type = Option<u32>)]
+    list: Option<ListId>,
+    /// `id`, `due`, or `priority`
+    #[serde(deserialize_with = "empty_as_none", serialize_with = "display")]
+    #[param(value_type = Option<String>)]
+    sort: Option<SortKey>,
+    /// Sort in descending order
+    #[serde(skip_serializing_if = "Not::not")]
+    desc: bool,
+    /// Show at most this many tasks
+    #[serde(deserialize_with = "empty_as_none")]
+    limit: Option<u32>,
+    /// Skip this many tasks
+    offset: u32,
+}
+
+impl IndexQuery {
+    fn task_query(&self) -> anyhow::Result<TaskQuery> {
+        Ok(TaskQuery {
+            complete: self.complete,
+            min_priority: self.min_priority,
+            max_priority: self.max_priority,
+            due_before: self.due_before.as_deref().map(date_from_str).transpose()?,
+            due_after: self.due_after.as_deref().map(date_from_str).transpose()?,
+            overdue: self.overdue,
+            text: self.q.clone(),
+            tags: self.tag.iter().cloned().collect(),
+            ready: self.ready,
+            list: self.list,
+            sort: self
+                .sort
+                .map(|key| Sort {
+                    key,
+                    descending: self.desc,
+                })
+                .into_iter()
+                .collect(),
+            limit: self.limit,
+            offset: self.offset,
+        })
+    }
+
+    /// Returns a link to the index with this query, but starting at `offset`.
+    fn with_offset(&self, offset: u32) -> anyhow::Result<String> {
+        let query = Self {
+            offset,
+            ..self.clone()
+        };
+
+        Ok(format!("/?{}", serde_urlencoded::to_string(query)?))
+    }
+}
+
+/// A task on the index page, indented under its parent if that's shown too.
+#[derive(Serialize)]
+struct NestedTask {
+    depth: usize,
+    #[serde(flatten)]
+    task: Task,
+}
+
+#[derive(Serialize)]
+struct IndexContext {
+    tasks: Vec<NestedTask>,
+    query: IndexQuery,
+    /// For the sidebar. Archived lists are left out, unless one is being shown.
+    lists: Vec<List>,
+    priorities: Vec<&'static str>,
+    previous_page: Option<String>,
+    next_page: Option<String>,
+    flash: Option<Flash>,
+    /// Where the page's script should put tasks added while it's open
+    /// (`append` or `prepend`), if they belong on this page at all.
+    new_tasks: Option<&'static str>,
+}
+
+#[debug_handler]
+pub(crate) async fn index(
+    Query(query): Query<IndexQuery>,
+    request_headers: HeaderMap,
+    tera: Extension<Arc<RwLock<Tera>>>,
+    connection_pool: Extension<ConnectionPool>,
+) -> Result<(HeaderMap, Html<String>), StatusCode> {
+    let task_query = query.task_query().map_err(|_| StatusCode::BAD_REQUEST)?;
+    let (flash, headers) = Flash::take(&request_headers);
+
+    // TODO: hack? need to manually intervene to swap
+    // `anyhow::Error` for `StatusCode::INTERNAL_SERVER_ERROR`
+    let result: anyhow::Result<Html<String>> = try {
+        let new_tasks = match (task_query.has_filters(), query.sort) {
+            (false, None) => Some("append"),
+            (false, Some(SortKey::Id)) if query.desc => Some("prepend"),
+            (false, Some(SortKey::Id)) => Some("append"),
+            _ => None,
+        };
+
+        let tasks = query_tasks(&connection_pool, task_query).await?;
+
+        let lists = fetch_lists(&connection_pool)
+            .await?
+            .into_iter()
+            .filter(|list| !list.archived || list.id == query.list)
+            .collect();
+
+        let (previous_page, next_page) = match query.limit {
+            Some(limit) => (
+                (query.offset > 0)
+                    .then(|| query.with_offset(query.offset.saturating_sub(limit)))
+                    .transpose()?,
+                (tasks.len() as u32 == limit)
+                    .then(|| query.with_offset(query.offset + limit))
+                    .transpose()?,
+            ),
+
+            None => (None, None),
+        };
+
+        let context = IndexContext {
+            tasks: nest_subtasks(tasks)
+                .into_iter()
+                .map(|(depth, task)| NestedTask { depth, task })
+                .collect(),
+            query,
+            lists,
+            priorities: Priority::ALL.iter().map(Priority::as_str).collect(),
+            previous_page,
+            next_page,
+            flash,
+            new_tasks,
+        };
+
+        axum_render(&tera, "index.html", context).await?
+    };
+
+    result
+        .map(|html| (headers, html))
+        .map_err(internal_error("rendering index.html"))
+}
+
+/// The checkboxes submitted by the form on the index page.
+/// Unchecked checkboxes aren't submitted at all, so the form also lists
+/// which tasks were shown.
+#[derive(Default)]
+struct IndexForm {
+    shown: BTreeSet<TaskId>,
+    complete: BTreeSet<TaskId>,
+    delete: BTreeSet<TaskId>,
+}
+
+impl IndexForm {
+    fn from_fields(fields: &[(String, String)]) -> anyhow::Result<Self> {
+        let mut res = Self::default();
+
+        for (name, value) in fields {
+            if name == "shown" {
+                res.shown.insert(TaskId::from_str(value)?);
+            } else if let Some(id) = name
+                .strip_prefix("task_")
+                .and_then(|name| name.strip_suffix("_complete"))
+            {
+                res.complete.insert(TaskId::from_str(id)?);
+            } else if let Some(id) = name.strip_prefix("delete_task_") {
+                res.delete.insert(TaskId::from_str(id)?);
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Turns the differences between the form and the current tasks
+    /// into messages for the server.
+    fn messages(&self, tasks: &[Task]) -> Vec<Message> {
+        let mut res = Vec::new();
+
+        let parents = tasks
+            .iter()
+            .filter_map(|task| Some((task.id?, task.parent?)))
+            .collect::<BTreeMap<_, _>>();
+
+        // Subtasks are removed along with their parent, so there's nothing
+        // to do for them
+        let parent_deleted = |mut id: TaskId| {
+            while let Some(&parent) = parents.get(&id) {
+                if self.delete.contains(&parent) {
+                    return true;
+                }
+
+                id = parent;
+            }
+
+            false
+        };
+
+        for task in tasks {
+            let id = match task.id {
+                Some(id) if self.shown.contains(&id) => id,
+                _ => continue,
+            };
+
+            if parent_deleted(id) {
+                continue;
+            }
+
+            if self.delete.contains(&id) {
+                res.push(Message::Remove(id));
+            } else if self.complete.contains(&id) != task.complete {
+                let delta = TaskDelta {
+                    complete: Delta::Changed(!task.complete),
+                    ..TaskDelta::default()
+                };
+
+                res.push(Message::Update(id, delta));
+            }
+        }
+
+        res
+    }
+}
+
+/// Returns a summary of what happened.
+async fn apply_index_form(
+    connection_pool: &ConnectionPool,
+    form: &IndexForm,
+) -> anyhow::Result<Flash> {
+    // Tasks might have been changed since the page was rendered,
+    // so diff against what the server has now
+    let tasks = query_tasks(connection_pool, TaskQuery::default()).await?;
+
+    let (mut updated, mut deleted) = (0, 0);
+    let mut errors = Vec::new();
+
+    let messages = form.messages(&tasks);
+
+    // Sent all at once, but each change can succeed or fail on its own
+    let batch = Message::Batch {
+        messages: messages.clone(),
+        atomic: false,
+    };
+
+    let responses = match connection_pool.send(batch).await? {
+        yabusame::Response::Batch(responses) => responses,
+        response => return Err(anyhow!("unexpected response {response:?}")),
+    };
+
+    for (message, response) in messages.iter().zip(responses) {
+        let (id, counter) = match message {
+            Message::Remove(id) => (*id, &mut deleted),
+            Message::Update(id, _) => (*id, &mut updated),
+            _ => unreachable!("only updates and removals are generated from the form"),
+        };
+
+        match response {
+            yabusame::Response::Error(err) => errors.push(format!("task {id}: {err}")),
+            _ => *counter += 1,
+        }
+    }
+
+    if !errors.is_empty() {
+        return Ok(Flash::error(format!(
+            "Some tasks couldn't be changed ({})",
+            errors.join("; ")
+        )));
+    }
+
+    let count = |n: usize| {
+        if n == 1 {
+            "1 task".to_string()
+        } else {
+            format!("{n} tasks")
+        }
+    };
+
+    let message = match (updated, deleted) {
+        (0, 0) => "Nothing to update".to_string(),
+        (updated, 0) => format!("Updated {}", count(updated)),
+        (0, deleted) => format!("Deleted {}", count(deleted)),
+        (updated, deleted) => format!("Updated {} and deleted {}", count(updated), count(deleted)),
+    };
+
+    Ok(Flash::success(message))
+}
+
+/// Handles the form on the index page, then redirects back to it.
+#[debug_handler]
+pub(crate) async fn update_tasks(
+    uri: Uri,
+    Form(fields): Form<Vec<(String, String)>>,
+    connection_pool: Extension<ConnectionPool>,
+) -> Result<(HeaderMap, Redirect), StatusCode> {
+    let form = IndexForm::from_fields(&fields).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let flash = apply_index_form(&connection_pool, &form)
+        .await
+        .unwrap_or_else(|err| {
+            internal_error("updating tasks")(err);
+            Flash::error("Couldn't update tasks; check yabusite's logs for details")
+        });
+
+    let headers = flash
+        .set_cookie_headers()
+        .map_err(internal_error("setting the flash message"))?;
+
+    // The form is posted to the page it's on, so this keeps any filters
+    let location = uri.path_and_query().map_or("/", |path| path.as_str());
+    Ok((headers, Redirect::to(location)))
+}
@@ -0,0 +1,485 @@
+//! Pages for viewing, creating, and editing individual tasks.
+
+use anyhow::anyhow;
+use axum::{
+    extract::{Form, Path, Query},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+    Extension,
+};
+use axum_macros::debug_handler;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+    sync::Arc,
+};
+use tera::Tera;
+use time::{format_description, OffsetDateTime, PrimitiveDateTime, UtcOffset};
+use tokio::sync::RwLock;
+use yabusame::{
+    list::{List, ListId},
+    recurrence::Recurrence,
+    validate_tag, Delta, Message, Priority, SetDelta, Task, TaskDelta, TaskId, YabuRpcError,
+};
+
+use super::{fetch_lists, fetch_task, internal_error};
+use crate::{flash::Flash, pool::ConnectionPool, tera_helpers::axum_render};
+
+/// The format used by `<input type="datetime-local">`.
+const DATETIME_LOCAL_FORMAT: &str = "[year]-[month]-[day]T[hour]:[minute]";
+
+/// Maps form fields to what's wrong with them.
+/// `form` is used for errors which aren't about a particular field.
+type FormErrors = BTreeMap<&'static str, String>;
+
+/// The fields of the forms for creating and editing tasks.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct TaskForm {
+    description: String,
+    priority: String,
+    /// In the browser's time zone, since `datetime-local` inputs
+    /// don't have one
+    due_date: String,
+    /// The browser's offset from UTC at `due_date`, in minutes.
+    /// Filled in by `task_form.js`; without it, `due_date` is taken as UTC.
+    due_date_offset: String,
+    /// Separated by whitespace or commas
+    tags: String,
+    /// In the syntax of `Recurrence::from_str`. Empty if the task doesn't repeat.
+    every: String,
+    /// The ID of the task this is a subtask of. Empty for top-level tasks.
+    parent: String,
+    /// IDs of the tasks this one depends on, separated by whitespace or commas
+    depends_on: String,
+    /// The ID of the list the task is in. Empty for tasks which aren't in one.
+    list: String,
+    /// Markdown. Empty for tasks without notes.
+    notes: String,
+    complete: bool,
+}
+
+/// A `TaskForm` which passed validation.
+struct ValidTask {
+    description: String,
+    priority: Priority,
+    due_date: Option<OffsetDateTime>,
+    tags: BTreeSet<String>,
+    /// Without `until` or `count`, which the form doesn't have fields for
+    recurrence: Option<Recurrence>,
+    parent: Option<TaskId>,
+    depends_on: BTreeSet<TaskId>,
+    list: Option<ListId>,
+    notes: Option<String>,
+    complete: bool,
+}
+
+impl TaskForm {
+    fn from_task(task: &Task) -> anyhow::Result<Self> {
+        let due_date = match task.due_date {
+            Some(due_date) => due_date
+                .to_offset(UtcOffset::UTC)
+                .format(&format_description::parse(DATETIME_LOCAL_FORMAT)?)?,
+            None => String::new(),
+        };
+
+        Ok(Self {
+            description: task.description.to_string(),
+            priority: task.priority.as_str().to_string(),
+            due_date,
+            due_date_offset: "0".to_string(),
+            tags: task.tags.iter().cloned().collect::<Vec<_>>().join(" "),
+            every: (task.recurrence.as_ref())
+                .map_or_else(String::new, |recurrence| recurrence.to_string()),
+            parent: task
+                .parent
+                .map_or_else(String::new, |parent| parent.to_string()),
+            depends_on: (task.depends_on.iter())
+                .map(|dependency| dependency.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            list: task.list.map_or_else(String::new, |list| list.to_string()),
+            notes: task.notes.clone().unwrap_or_default(),
+            complete: task.complete,
+        })
+    }
+
+    fn parse_due_date(&self) -> anyhow::Result<Option<OffsetDateTime>> {
+        let due_date = self.due_date.trim();
+
+        if due_date.is_empty() {
+            return Ok(None);
+        }
+
+        // Browsers add seconds if the user enters them
+        let with_seconds = format!("{DATETIME_LOCAL_FORMAT}:[second]");
+        let without_seconds = format_description::parse(DATETIME_LOCAL_FORMAT)?;
+        let with_seconds = format_description::parse(&with_seconds)?;
+        let date_time = PrimitiveDateTime::parse(due_date, &without_seconds)
+            .or_else(|_| PrimitiveDateTime::parse(due_date, &with_seconds))?;
+
+        let offset_minutes = match self.due_date_offset.trim() {
+            "" => 0,
+            offset => i32::from_str(offset)?,
+        };
+
+        let offset = UtcOffset::from_whole_seconds(offset_minutes * 60)?;
+        Ok(Some(date_time.assume_offset(offset)))
+    }
+
+    fn validate(&self) -> Result<ValidTask, FormErrors> {
+        let mut errors = FormErrors::new();
+
+        let description = self.description.trim();
+
+        if description.is_empty() {
+            errors.insert("description", "Tasks need a description".to_string());
+        }
+
+        let priority = Priority::from_str(&self.priority)
+            .map_err(|err| errors.insert("priority", err.to_string()))
+            .ok();
+
+        let due_date = self
+            .parse_due_date()
+            .map_err(|_| errors.insert("due_date", "Not a valid date and time".to_string()))
+            .ok()
+            .flatten();
+
+        let tags = self
+            .tags
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| validate_tag(tag).map(|_| tag.to_string()))
+            .collect::<Result<_, _>>()
+            .map_err(|err| errors.insert("tags", err.to_string()))
+            .ok();
+
+        let recurrence = match self.every.trim() {
+            "" => Some(None),
+            every => Recurrence::from_str(every)
+                .map(Some)
+                .map_err(|err| errors.insert("every", err.to_string()))
+                .ok(),
+        };
+
+        let parent = match self.parent.trim() {
+            "" => Some(None),
+            parent => TaskId::from_str(parent)
+                .map(Some)
+                .map_err(|_| errors.insert("parent", "Not a task ID".to_string()))
+                .ok(),
+        };
+
+        let depends_on = self
+            .depends_on
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|task_id| !task_id.is_empty())
+            .map(|task_id| TaskId::from_str(task_id.trim_start_matches('#')))
+            .collect::<Result<_, _>>()
+            .map_err(|_| errors.insert("depends_on", "Not a list of task IDs".to_string()))
+            .ok();
+
+        // Leading whitespace can be meaningful in Markdown
+        let notes = match self.notes.trim_end() {
+            "" => None,
+            notes => Some(notes.to_string()),
+        };
+
+        let list = match self.list.trim() {
+            "" => Some(None),
+            list => ListId::from_str(list)
+                .map(Some)
+                .map_err(|_| errors.insert("list", "Not a list ID".to_string()))
+                .ok(),
+        };
+
+        match (priority, tags, recurrence, parent, depends_on, list) {
+            (
+                Some(priority),
+                Some(tags),
+                Some(recurrence),
+                Some(parent),
+                Some(depends_on),
+                Some(list),
+            ) if errors.is_empty() => Ok(ValidTask {
+                description: description.to_string(),
+                priority,
+                due_date,
+                tags,
+                recurrence,
+                parent,
+                depends_on,
+                list,
+                notes,
+                complete: self.complete,
+            }),
+
+            _ => Err(errors),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TaskFormContext {
+    /// `None` when creating a task
+    task_id: Option<TaskId>,
+    form: TaskForm,
+    errors: FormErrors,
+    priorities: Vec<&'static str>,
+    /// The lists the task can be put in. Archived lists are left out,
+    /// unless the task is already in one.
+    lists: Vec<List>,
+}
+
+async fn render_form(
+    tera: &RwLock<Tera>,
+    connection_pool: &ConnectionPool,
+    task_id: Option<TaskId>,
+    form: TaskForm,
+    errors: FormErrors,
+) -> anyhow::Result<Response> {
+    let status = if errors.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::UNPROCESSABLE_ENTITY
+    };
+
+    let current_list = ListId::from_str(form.list.trim()).ok();
+    let lists = fetch_lists(connection_pool)
+        .await?
+        .into_iter()
+        .filter(|list| !list.archived || list.id == current_list)
+        .collect();
+
+    let context = TaskFormContext {
+        task_id,
+        form,
+        errors,
+        priorities: Priority::ALL.iter().map(Priority::as_str).collect(),
+        lists,
+    };
+
+    Ok((status, axum_render(tera, "task_form.html", context).await?).into_response())
+}
+
+/// Redirects to `location`, showing `message` once there.
+fn redirect_with_flash(location: &str, message: &str) -> anyhow::Result<Response> {
+    let headers = Flash::success(message).set_cookie_headers()?;
+    Ok((headers, Redirect::to(location)).into_response())
+}
+
+#[derive(Serialize)]
+struct TaskContext {
+    task: Task,
+    /// `task.recurrence` as text
+    every: Option<String>,
+    /// The list the task is in
+    list: Option<List>,
+    flash: Option<Flash>,
+}
+
+#[debug_handler]
+pub(crate) async fn show(
+    Path(task_id): Path<TaskId>,
+    request_headers: HeaderMap,
+    tera: Extension<Arc<RwLock<Tera>>>,
+    connection_pool: Extension<ConnectionPool>,
+) -> Result<Response, StatusCode> {
+    let result: anyhow::Result<Response> = try {
+        match fetch_task(&connection_pool, task_id).await? {
+            Some(task) => {
+                let (flash, headers) = Flash::take(&request_headers);
+                let every = (task.recurrence.as_ref()).map(|recurrence| recurrence.to_string());
+                let list = match task.list {
+                    Some(list_id) => fetch_lists(&connection_pool)
+                        .await?
+                        .into_iter()
+                        .find(|list| list.id == Some(list_id)),
+                    None => None,
+                };
+
+                let context = TaskContext {
+                    task,
+                    every,
+                    list,
+                    flash,
+                };
+                let html = axum_render(&tera, "task.html", context).await?;
+                (headers, html).into_response()
+            }
+
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    };
+
+    result.map_err(internal_error("rendering task.html"))
+}
+
+/// Lets links fill in some of the form for a new task.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct NewTaskQuery {
+    /// The ID of the list to add the task to
+    list: String,
+}
+
+#[debug_handler]
+pub(crate) async fn new_form(
+    Query(query): Query<NewTaskQuery>,
+    tera: Extension<Arc<RwLock<Tera>>>,
+    connection_pool: Extension<ConnectionPool>,
+) -> Result<Response, StatusCode> {
+    let form = TaskForm {
+        priority: Priority::Medium.as_str().to_string(),
+        list: query.list,
+        ..TaskForm::default()
+    };
+
+    render_form(&tera, &connection_pool, None, form, FormErrors::new())
+        .await
+        .map_err(internal_error("rendering task_form.html"))
+}
+
+#[debug_handler]
+pub(crate) async fn create(
+    tera: Extension<Arc<RwLock<Tera>>>,
+    connection_pool: Extension<ConnectionPool>,
+    Form(form): Form<TaskForm>,
+) -> Result<Response, StatusCode> {
+    let result: anyhow::Result<Response> = try {
+        match form.validate() {
+            Ok(valid) => {
+                let task = Task {
+                    tags: valid.tags,
+                    recurrence: valid.recurrence,
+                    parent: valid.parent,
+                    depends_on: valid.depends_on,
+                    list: valid.list,
+                    notes: valid.notes,
+                    ..Task::new(
+                        None,
+                        valid.complete,
+                        valid.description,
+                        valid.priority,
+                        valid.due_date,
+                    )
+                };
+
+                match connection_pool.send(Message::Add(task)).await? {
+                    yabusame::Response::Task(task) => {
+                        let task_id = task.id_or_error()?;
+                        redirect_with_flash(&format!("/tasks/{task_id}"), "Added task")?
+                    }
+
+                    yabusame::Response::Error(err) => {
+                        let errors = FormErrors::from([("form", err.to_string())]);
+                        render_form(&tera, &connection_pool, None, form, errors).await?
+                    }
+
+                    response => Err(anyhow!("unexpected response {response:?}"))?,
+                }
+            }
+
+            Err(errors) => render_form(&tera, &connection_pool, None, form, errors).await?,
+        }
+    };
+
+    result.map_err(internal_error("creating a task"))
+}
+
+#[debug_handler]
+pub(crate) async fn edit_form(
+    Path(task_id): Path<TaskId>,
+    tera: Extension<Arc<RwLock<Tera>>>,
+    connection_pool: Extension<ConnectionPool>,
+) -> Result<Response, StatusCode> {
+    let result: anyhow::Result<Response> = try {
+        match fetch_task(&connection_pool, task_id).await? {
+            Some(task) => {
+                let form = TaskForm::from_task(&task)?;
+                render_form(
+                    &tera,
+                    &connection_pool,
+                    Some(task_id),
+                    form,
+                    FormErrors::new(),
+                )
+                .await?
+            }
+
+            None => StatusCode::NOT_FOUND.into_response(),
+        }
+    };
+
+    result.map_err(internal_error("rendering task_form.html"))
+}
+
+#[debug_handler]
+pub(crate) async fn update(
+    Path(task_id): Path<TaskId>,
+    tera: Extension<Arc<RwLock<Tera>>>,
+    connection_pool: Extension<ConnectionPool>,
+    Form(form): Form<TaskForm>,
+) -> Result<Response, StatusCode> {
+    let result: anyhow::Result<Response> = try {
+        match form.validate() {
+            Ok(valid) => {
+                let previous = match fetch_task(&connection_pool, task_id).await? {
+                    Some(task) => task.recurrence,
+                    None => return Ok(StatusCode::NOT_FOUND.into_response()),
+                };
+
+                // Keeps any `until` or `count` the rule was given with `yabu`
+                let same_rule = |previous: &Recurrence, new: &Recurrence| {
+                    previous.to_string() == new.to_string()
+                };
+
+                let recurrence = match (previous, valid.recurrence) {
+                    (Some(previous), Some(new)) if same_rule(&previous, &new) => Delta::Unchanged,
+                    (_, new) => Delta::Changed(new),
+                };
+
+                let delta = TaskDelta {
+                    complete: Delta::Changed(valid.complete),
+                    description: Delta::Changed(valid.description.into()),
+                    priority: Delta::Changed(valid.priority),
+                    due_date: Delta::Changed(valid.due_date),
+                    tags: vec![SetDelta::Replace(valid.tags)],
+                    recurrence,
+                    parent: Delta::Changed(valid.parent),
+                    depends_on: vec![SetDelta::Replace(valid.depends_on)],
+                    list: Delta::Changed(valid.list),
+                    notes: Delta::Changed(valid.notes),
+                };
+
+                match connection_pool
+                    .send(Message::Update(task_id, delta))
+                    .await?
+                {
+                    yabusame::Response::Task(_) => {
+                        redirect_with_flash(&format!("/tasks/{task_id}"), "Saved task")?
+                    }
+
+                    yabusame::Response::Error(YabuRpcError::TaskDoesntExist(_)) => {
+                        StatusCode::NOT_FOUND.into_response()
+                    }
+
+                    yabusame::Response::Error(err) => {
+                        let errors = FormErrors::from([("form", err.to_string())]);
+                        render_form(&tera, &connection_pool, Some(task_id), form, errors).await?
+                    }
+
+                    response => Err(anyhow!("unexpected response {response:?}"))?,
+                }
+            }
+
+            Err(errors) => {
+                render_form(&tera, &connection_pool, Some(task_id), form, errors).await?
+            }
+        }
+    };
+
+    result.map_err(internal_error("updating a task"))
+}
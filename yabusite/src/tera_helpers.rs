@@ -1,5 +1,6 @@
 use axum::response::Html;
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use pulldown_cmark::{html, Options, Parser};
 use serde::Serialize;
 use serde_json::{to_value, Value};
 use std::{collections::HashMap, path::Path, sync::Arc};
@@ -56,3 +57,16 @@ pub fn date_time(value: &Value, _args: &HashMap<String, Value>) -> Result<Value,
 
     to_value(&date_time.format(&DATE_TIME_FORMAT).unwrap()).map_err(tera::Error::json)
 }
+
+/// Renders Markdown as HTML, sanitised so that it can be marked `safe`.
+pub fn markdown(value: &Value, _args: &HashMap<String, Value>) -> Result<Value, tera::Error> {
+    let markdown = value
+        .as_str()
+        .ok_or_else(|| tera::Error::msg("`markdown` only takes strings"))?;
+
+    let mut unsafe_html = String::new();
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+
+    to_value(ammonia::clean(&unsafe_html)).map_err(tera::Error::json)
+}
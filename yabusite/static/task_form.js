@@ -0,0 +1,26 @@
+// `datetime-local` inputs don't have a time zone. Their values are sent along
+// with the browser's UTC offset (in minutes) so that the server can make sense
+// of them, and values from the server are converted to the browser's time zone.
+
+function pad(n) {
+  return String(n).padStart(2, "0");
+}
+
+function toDateTimeLocal(date) {
+  return `${date.getFullYear()}-${pad(date.getMonth() + 1)}-${pad(date.getDate())}`
+    + `T${pad(date.getHours())}:${pad(date.getMinutes())}`;
+}
+
+for (const input of document.querySelectorAll("input[data-offset-input]")) {
+  const offsetInput = document.getElementById(input.dataset.offsetInput);
+
+  if (input.value) {
+    const offset = Number(offsetInput.value) || 0;
+    const utc = Date.parse(`${input.value}Z`) - offset * 60 * 1000;
+    input.value = toDateTimeLocal(new Date(utc));
+  }
+
+  input.form.addEventListener("submit", () => {
+    offsetInput.value = input.value ? -new Date(input.value).getTimezoneOffset() : 0;
+  });
+}